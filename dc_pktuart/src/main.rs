@@ -4,54 +4,36 @@ extern crate log;
 mod pkt_uart;
 
 
-use devconsole::{ChannelID, DCClient};
-use devconsole_serial_protocol::SerialEvent;
-use log::debug;
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use devconsole::{ChannelID, DCClient, NodeMeta};
+use devconsole_serial_protocol::pktuart::{BridgeRequest, CONTROL_CHANNEL_NAME};
+use log::{debug, error, warn};
 use tokio::{
-    select, spawn, sync::mpsc::{self}
+    spawn, sync::mpsc::{self}
 };
 
 use crate::pkt_uart::{PktUARTRx, PktUARTTx};
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Request {
-    // from_channel --> to_channel
-    src: ChannelID,
-    dst_ch_name: String,
-}
-
-#[derive(Debug)]
-struct DCSendRequest {
-    dst: ChannelID,
-    data: Vec<u8>,
-}
-
-#[derive(Debug)]
 struct DCTxPort {
-    tx: mpsc::Sender<DCSendRequest>,
+    client: DCClient,
     channel_id: ChannelID,
 }
 
 impl DCTxPort {
     pub async fn send(&self, data: Vec<u8>) {
-        let req = DCSendRequest {
-            dst: self.channel_id,
-            data,
-        };
-        if let Err(e) = self.tx.send(req).await {
-            debug!("Failed to send data to DCTxPort: {e}");
+        if let Err(e) = self.client.send_bin(self.channel_id, data).await {
+            debug!("Failed to send data to DC: {e}");
         }
     }
 
     pub fn to_mpsc(&self) -> mpsc::Sender<Vec<u8>> {
         let (tx, mut rx) = mpsc::channel(100);
-        let tx_dc = self.tx.clone();
+        let client = self.client.clone();
         let dst = self.channel_id;
         spawn(async move {
             while let Some(data) = rx.recv().await {
-                let req = DCSendRequest { dst, data };
-                if let Err(e) = tx_dc.send(req).await {
+                if let Err(e) = client.send_bin(dst, data).await {
                     debug!("Failed to send data to DC: {e}");
                 }
             }
@@ -103,56 +85,62 @@ pub async fn main() {
         .format_target(false)
         .init();
 
-    let mut client = DCClient::new("ws://127.0.0.1:9001").await.unwrap();
-
-    let (tx, mut rx) = mpsc::channel(100);
+    let client =
+        DCClient::new_with_meta("ws://127.0.0.1:9001", NodeMeta::new("pktuart", "bridge"))
+            .await
+            .unwrap();
 
-    let ctrl_cid = client.open("PktUART".to_string()).await.unwrap();
-    client
-        .listen(ctrl_cid, Some(tx.clone()), None)
+    let ctrl_cid = client
+        .open_persistent_typed::<BridgeRequest>(CONTROL_CHANNEL_NAME.to_string())
         .await
         .unwrap();
-    let (dc_tx, mut dc_rx) = mpsc::channel(100);
-
-    loop {
-        select! {
-            a = rx.recv() => {
-                let (channel, message) = a.unwrap();
-
-                debug!("Received message from {message} on channel {channel}");
-
-                if let Ok(req) = serde_json::from_str::<Request>(&message) {
-                    debug!("Parsed request: {req:?}");
-
-                    let dst_ch_id = client.open(req.dst_ch_name.clone()).await.unwrap();
-
-                    let (tx_src, rx_src) = mpsc::channel(100);
-                    let (tx_dst, rx_dst) = mpsc::channel(100);
-
-                    client.listen(req.src, None, Some(tx_src)).await.unwrap();
-                    client.listen(dst_ch_id, None, Some(tx_dst)).await.unwrap();
-
-                    spawn(pktuart_decoder(rx_src, DCTxPort {
-                        tx: dc_tx.clone(),
-                        channel_id: dst_ch_id,
-                    }));
-                    spawn(pktuart_encoder(rx_dst, DCTxPort {
-                        tx: dc_tx.clone(),
-                        channel_id: req.src,
-                    }));
-                } else if let Ok(event) = serde_json::from_str::<SerialEvent>(&message) {
-                    debug!("Parsed serial event: {event:?}");
-                } else {
-                    debug!("Unknown message format");
+    let mut requests = client.subscribe_typed::<BridgeRequest>(ctrl_cid).await.unwrap();
+
+    // bridges already set up, so that a repeated request does not duplicate packets
+    let mut bridges: HashMap<(ChannelID, String), ChannelID> = HashMap::new();
+
+    while let Some(request) = requests.recv().await {
+        match request {
+            Ok(req) => {
+                debug!("Parsed request: {req:?}");
+
+                let bridge = (req.src, req.dst_ch_name.clone());
+                if let Some(dst_ch_id) = bridges.get(&bridge) {
+                    debug!("Already bridging {} to {dst_ch_id}", req.src);
+                    continue;
                 }
-            }
 
-            b = dc_rx.recv() => {
-                let req = b.unwrap();
-                client.send_bin(req.dst, req.data).await.unwrap();
-            }
+                let dst_ch_id = match client.open_persistent(req.dst_ch_name.clone()).await {
+                    Ok(dst_ch_id) => dst_ch_id,
+                    Err(e) => {
+                        error!("Failed to open channel {}: {e}", req.dst_ch_name);
+                        continue;
+                    }
+                };
 
+                let (tx_src, rx_src) = mpsc::channel(100);
+                let (tx_dst, rx_dst) = mpsc::channel(100);
 
+                if let Err(e) = client.listen(req.src, None, Some(tx_src)).await {
+                    error!("Failed to listen to channel {}: {e}", req.src);
+                    continue;
+                }
+                if let Err(e) = client.listen(dst_ch_id, None, Some(tx_dst)).await {
+                    error!("Failed to listen to channel {dst_ch_id}: {e}");
+                    continue;
+                }
+
+                spawn(pktuart_decoder(rx_src, DCTxPort {
+                    client: client.clone(),
+                    channel_id: dst_ch_id,
+                }));
+                spawn(pktuart_encoder(rx_dst, DCTxPort {
+                    client: client.clone(),
+                    channel_id: req.src,
+                }));
+                bridges.insert(bridge, dst_ch_id);
+            }
+            Err(e) => warn!("Ignoring malformed request: {e}"),
         }
     }
 }
@@ -0,0 +1,120 @@
+//! Synchronous wrapper of [`crate::DCClient`] for tools and tests without an async runtime.
+
+use std::time::Duration;
+
+use tokio::{
+    runtime::{self, Runtime},
+    sync::mpsc,
+};
+use tokio_tungstenite::tungstenite;
+
+use crate::{ChannelID, ChannelInfo, DCClientError, Message, NodeID, NodeMeta};
+
+// How long dropping a client waits for the server to acknowledge the close.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Blocking DevConsole client. It owns a small runtime that keeps the connection
+/// running in the background, so it must not be used from within an async context.
+pub struct DCClient {
+    runtime: Runtime,
+    client: crate::DCClient,
+    received_tx: mpsc::UnboundedSender<Message>,
+    received: mpsc::UnboundedReceiver<Message>,
+}
+
+impl DCClient {
+    pub fn new(url: &str) -> Result<Self, DCClientError> {
+        DCClient::new_with_meta(url, NodeMeta::from_env("client"))
+    }
+
+    pub fn new_with_meta(url: &str, meta: NodeMeta) -> Result<Self, DCClientError> {
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .map_err(|e| DCClientError::Connect(Box::new(tungstenite::Error::Io(e))))?;
+        let client = runtime.block_on(crate::DCClient::new_with_meta(url, meta))?;
+        let (received_tx, received) = mpsc::unbounded_channel();
+
+        Ok(DCClient {
+            runtime,
+            client,
+            received_tx,
+            received,
+        })
+    }
+
+    pub fn open(&self, name: &str) -> Result<ChannelID, DCClientError> {
+        self.runtime.block_on(self.client.open(name.to_string()))
+    }
+
+    pub fn send(&self, channel: ChannelID, data: &str) -> Result<(), DCClientError> {
+        self.runtime
+            .block_on(self.client.send(channel, data.to_string()))
+    }
+
+    pub fn send_bin(&self, channel: ChannelID, data: &[u8]) -> Result<(), DCClientError> {
+        self.runtime
+            .block_on(self.client.send_bin(channel, data.to_vec()))
+    }
+
+    /// Starts receiving the data of `channel` into the queue read by [`DCClient::recv_timeout`].
+    ///
+    /// Received messages are buffered without limit until they are read.
+    pub fn listen(&self, channel: ChannelID) -> Result<(), DCClientError> {
+        let mut subscription = self.runtime.block_on(self.client.subscribe(channel))?;
+        let received_tx = self.received_tx.clone();
+        self.runtime.spawn(async move {
+            while let Some(message) = subscription.recv().await {
+                if received_tx.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for a message on any listened channel.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Message, DCClientError> {
+        let received = self
+            .runtime
+            .block_on(async { tokio::time::timeout(timeout, self.received.recv()).await });
+        match received {
+            Ok(Some(message)) => Ok(message),
+            // never happens while `received_tx` is alive
+            Ok(None) => Err(DCClientError::ConnectionBroken),
+            Err(_) if !self.client.is_connected() => Err(DCClientError::ConnectionBroken),
+            Err(_) => Err(DCClientError::Timeout),
+        }
+    }
+
+    pub fn channel_list(&self) -> Result<Vec<ChannelID>, DCClientError> {
+        self.runtime.block_on(self.client.channel_list())
+    }
+
+    pub fn channel_info(&self, channel: ChannelID) -> Result<ChannelInfo, DCClientError> {
+        self.runtime.block_on(self.client.channel_info(channel))
+    }
+
+    pub fn get_node_id(&self) -> Option<NodeID> {
+        self.runtime.block_on(self.client.get_node_id())
+    }
+
+    /// The underlying async client, e.g. to be used with [`DCClient::block_on`].
+    pub fn as_async(&self) -> &crate::DCClient {
+        &self.client
+    }
+
+    /// Runs a future on the client's runtime.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+}
+
+impl Drop for DCClient {
+    fn drop(&mut self) {
+        self.runtime
+            .block_on(self.client.close_timeout(CLOSE_TIMEOUT));
+    }
+}
@@ -1,19 +1,32 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
+    future::Future,
     ops::{Deref, DerefMut},
+    pin::Pin,
     sync::Arc,
+    time::Duration,
 };
 
-use crate::{ChannelID, ChannelInfo, Event, NodeID};
+use crate::{
+    CallID, ChannelID, ChannelInfo, ChannelStream, Delivery, Directs, Event, NodeID, NodeInfo,
+    NodeMeta, Schema, Subscription, TypedSubscription,
+    subscription::{self, Subscriber},
+};
 use futures_util::{
-    SinkExt, StreamExt,
-    future::ready,
-    stream::{SplitSink, SplitStream},
+    FutureExt, SinkExt, Stream, StreamExt,
+    future::{BoxFuture, Shared, ready},
+    stream::{self, SplitSink},
 };
 use log::{info, warn};
+use serde::{Serialize, de::DeserializeOwned};
 use tokio::{
     net::TcpStream,
-    sync::{Mutex, mpsc, oneshot},
+    sync::{
+        Mutex, Notify,
+        mpsc::{self, error::TrySendError},
+        oneshot, watch,
+    },
+    task::AbortHandle,
 };
 use tokio_tungstenite::{
     MaybeTlsStream, WebSocketStream, connect_async,
@@ -23,11 +36,78 @@ use tokio_tungstenite::{
 extern crate env_logger as logger;
 extern crate log;
 
+type WsSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+// How long `close()` waits for the server to acknowledge the close.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+// Items the reader task hands to the delivery task before it waits.
+const INBOUND_BUFFER: usize = 256;
+pub(crate) type WriteResult = Result<(), tungstenite::Error>;
+
+/// Where the writer task sends events to.
+enum Link {
+    WebSocket(WsSink),
+    /// In-memory link to a server in the same process.
+    Loopback(mpsc::UnboundedSender<Event>),
+}
+
+impl Link {
+    async fn send(&mut self, event: Event) -> WriteResult {
+        match self {
+            Link::WebSocket(sink) => {
+                let msg = serde_json::to_string(&event)
+                    .map_err(|e| tungstenite::Error::Io(std::io::Error::other(e)))?;
+                sink.send(Message::Text(msg.into())).await
+            }
+            Link::Loopback(tx) => tx
+                .send(event)
+                .map_err(|_| tungstenite::Error::ConnectionClosed),
+        }
+    }
+
+    async fn close(self) {
+        match self {
+            Link::WebSocket(mut sink) => {
+                let _ = sink.close().await;
+            }
+            // dropping the sender ends the connection on the server side
+            Link::Loopback(_) => {}
+        }
+    }
+}
+
+/// Items handed from the reader task to the delivery task.
+enum Inbound {
+    Message(subscription::Message),
+    SupplierState(ChannelID, SupplierState),
+    Channel(ChannelEvent),
+}
+
+/// Commands for the writer task.
+enum Outgoing {
+    /// An event to write, with a slot for the result of the write.
+    Event {
+        event: Event,
+        sent: oneshot::Sender<WriteResult>,
+    },
+    /// Sends a Close frame and stops writing.
+    Close,
+}
+
+type RpcResult = Result<Vec<u8>, String>;
+type RpcHandler =
+    Arc<dyn Fn(Vec<u8>) -> Pin<Box<dyn Future<Output = RpcResult> + Send>> + Send + Sync>;
+
+type Waiter<T> = oneshot::Sender<Result<T, DCClientError>>;
+
 #[derive(Eq, Hash, PartialEq, Debug)]
 enum DispatchID {
     Listen(ChannelID),
+    ChannelOpen,
     ChannelList,
     ChannelInfo(ChannelID),
+    NodeInfo(NodeID),
+    ChannelWatch,
     None,
 }
 
@@ -40,34 +120,51 @@ impl From<&Event> for DispatchID {
             Event::ChannelListResponse { .. } => DispatchID::ChannelList,
             Event::ChannelInfoRequest(channel) => DispatchID::ChannelInfo(*channel),
             Event::ChannelInfoResponse(info) => DispatchID::ChannelInfo(info.channel),
-
-            Event::Data { channel, .. } => {
-                panic!("Data event should not be dispatched: {channel:?}")
+            Event::NodeInfoRequest(node_id) => DispatchID::NodeInfo(*node_id),
+            Event::NodeInfoResponse(info) => DispatchID::NodeInfo(info.node_id),
+            Event::ChannelOpenRequest { .. } | Event::ChannelOpenResponse { .. } => {
+                DispatchID::ChannelOpen
             }
-            Event::DataBin { channel, .. } => {
-                panic!("DataBin event should not be dispatched: {channel:?}")
+            Event::ChannelWatchRequest | Event::ChannelWatchResponse { .. } => {
+                DispatchID::ChannelWatch
             }
 
-            Event::NodeIDNotification { node_id: _ }
-            | Event::ChannelOpenRequest { name: _ }
-            | Event::ChannelOpenResponse {
-                channel: _,
-                success: _,
-            }
-            | Event::ChannelCloseRequest { channel: _ } => DispatchID::None,
+            // nothing waits for these
+            Event::Data { .. }
+            | Event::DataBin { .. }
+            | Event::Direct { .. }
+            | Event::DirectBin { .. }
+            | Event::NodeIDNotification { .. }
+            | Event::NodeMetaNotification(_)
+            | Event::ChannelSupplierDown { .. }
+            | Event::ChannelSupplierUp { .. }
+            | Event::ChannelOpened(_)
+            | Event::ChannelClosed(_)
+            | Event::RpcRequest { .. }
+            | Event::RpcResponse { .. }
+            | Event::RequestFailed { .. }
+            | Event::ChannelUnlistenRequest { .. }
+            | Event::ChannelCloseRequest { .. } => DispatchID::None,
         }
     }
 }
 
+// The server answers requests of a connection in order, so responses of the same
+// kind are matched to their waiters first-in first-out.
 #[derive(Default)]
 struct Dispatchers {
-    events: HashMap<DispatchID, oneshot::Sender<bool>>,
-    resolve_channel: Option<oneshot::Sender<ChannelID>>,
-    channel_list: Option<oneshot::Sender<Vec<ChannelID>>>,
-    channel_info: Option<oneshot::Sender<ChannelInfo>>,
-    data_handlers: HashMap<ChannelID, mpsc::Sender<(ChannelID, String)>>,
-    bin_data_handlers: HashMap<ChannelID, mpsc::Sender<(ChannelID, Vec<u8>)>>,
+    events: HashMap<DispatchID, VecDeque<Waiter<()>>>,
+    resolve_channel: VecDeque<Waiter<ChannelID>>,
+    channel_list: VecDeque<Waiter<Vec<ChannelID>>>,
+    channel_info: VecDeque<Waiter<ChannelInfo>>,
+    node_info: VecDeque<Waiter<NodeInfo>>,
+    supplier_handlers: HashMap<ChannelID, mpsc::Sender<(ChannelID, SupplierState)>>,
+    channel_watchers: Vec<mpsc::UnboundedSender<ChannelEvent>>,
+    rpc_handlers: HashMap<ChannelID, RpcHandler>,
+    pending_calls: HashMap<CallID, oneshot::Sender<RpcResult>>,
+    next_call_id: CallID,
     node_id: Option<NodeID>,
+    closed: bool,
 }
 
 struct SharedDispatchers(Arc<Mutex<Dispatchers>>);
@@ -99,100 +196,127 @@ impl Clone for SharedDispatchers {
 }
 
 impl SharedDispatchers {
-    pub async fn wait_for_event(&self, id: DispatchID) -> bool {
-        let (tx, rx) = oneshot::channel();
-
-        self.lock().await.events.insert(id, tx);
-
-        rx.await
-            .expect("Failed to receive response for channel listen request")
-    }
-
-    pub async fn dispatch_event(&self, id: DispatchID, success: bool) {
-        if let Some(tx) = self.lock().await.events.remove(&id) {
-            let _ = tx.send(success);
+    pub async fn dispatch_event(&self, id: DispatchID, result: Result<(), DCClientError>) {
+        let waiter = self
+            .lock()
+            .await
+            .events
+            .get_mut(&id)
+            .and_then(VecDeque::pop_front);
+        if let Some(tx) = waiter {
+            let _ = tx.send(result);
         } else {
             warn!("No dispatcher found for event: {id:?}");
         }
     }
 
-    pub async fn wait_for_channel(&self) -> ChannelID {
-        let (tx, rx) = oneshot::channel();
-        self.lock().await.resolve_channel = Some(tx);
-
-        rx.await.expect("Failed to receive channel ID")
-    }
-
-    pub async fn dispatch_channel(&self, channel: ChannelID) {
-        if let Some(tx) = self.lock().await.resolve_channel.take() {
+    pub async fn dispatch_channel(&self, channel: Result<ChannelID, DCClientError>) {
+        if let Some(tx) = self.lock().await.resolve_channel.pop_front() {
             let _ = tx.send(channel);
         } else {
             warn!("No dispatcher found for channel resolution");
         }
     }
 
-    pub async fn wait_for_channel_list(&self) -> Vec<ChannelID> {
-        let (tx, rx) = oneshot::channel();
-        self.lock().await.channel_list = Some(tx);
-
-        rx.await.expect("Failed to receive channel list")
-    }
-
-    pub async fn dispatch_channel_list(&self, channels: Vec<ChannelID>) {
-        if let Some(tx) = self.lock().await.channel_list.take() {
+    pub async fn dispatch_channel_list(&self, channels: Result<Vec<ChannelID>, DCClientError>) {
+        if let Some(tx) = self.lock().await.channel_list.pop_front() {
             let _ = tx.send(channels);
         } else {
             warn!("No dispatcher found for channel list");
         }
     }
 
-    pub async fn dispatch_channel_info(&self, info: ChannelInfo) {
-        if let Some(tx) = self.lock().await.channel_info.take() {
+    pub async fn dispatch_channel_info(&self, info: Result<ChannelInfo, DCClientError>) {
+        if let Some(tx) = self.lock().await.channel_info.pop_front() {
             let _ = tx.send(info);
         } else {
             warn!("No dispatcher found for channel info");
         }
     }
 
-    pub async fn wait_for_channel_info(&self) -> ChannelInfo {
-        let (tx, rx) = oneshot::channel();
-        self.lock().await.channel_info = Some(tx);
+    pub async fn dispatch_node_info(&self, info: Result<NodeInfo, DCClientError>) {
+        if let Some(tx) = self.lock().await.node_info.pop_front() {
+            let _ = tx.send(info);
+        } else {
+            warn!("No dispatcher found for node info");
+        }
+    }
 
-        rx.await.expect("Failed to receive channel info")
+    /// Fails the waiter of a request the server could not serve.
+    pub async fn dispatch_failure(&self, request: &Event, reason: String) {
+        let error = DCClientError::Server(reason);
+        match DispatchID::from(request) {
+            DispatchID::ChannelOpen => self.dispatch_channel(Err(error)).await,
+            DispatchID::ChannelList => self.dispatch_channel_list(Err(error)).await,
+            DispatchID::ChannelInfo(_) => self.dispatch_channel_info(Err(error)).await,
+            DispatchID::NodeInfo(_) => self.dispatch_node_info(Err(error)).await,
+            id @ (DispatchID::Listen(_) | DispatchID::ChannelWatch) => {
+                self.dispatch_event(id, Err(error)).await
+            }
+            DispatchID::None => warn!("Server failed to handle {request:?}: {error}"),
+        }
     }
 
-    pub async fn register_data_handler(
+    pub async fn register_supplier_handler(
         &self,
         channel: ChannelID,
-        handler: mpsc::Sender<(ChannelID, String)>,
+        handler: mpsc::Sender<(ChannelID, SupplierState)>,
     ) {
-        self.lock().await.data_handlers.insert(channel, handler);
+        self.lock().await.supplier_handlers.insert(channel, handler);
     }
 
-    pub async fn dispatch_data(&self, channel: ChannelID, data: String) {
-        if let Some(handler) = self.lock().await.data_handlers.get(&channel) {
-            let _ = handler.send((channel, data)).await;
+    pub async fn dispatch_supplier_state(&self, channel: ChannelID, state: SupplierState) {
+        // not awaiting the watcher under the lock, which the reader task needs
+        let handler = self.lock().await.supplier_handlers.get(&channel).cloned();
+        if let Some(handler) = handler {
+            let _ = handler.send((channel, state)).await;
         } else {
-            warn!("No data handler found for channel: {channel}");
+            info!("Supplier of channel {channel} is {state:?}");
         }
     }
 
-    pub async fn register_bin_data_handler(
+    pub async fn dispatch_channel_event(&self, event: ChannelEvent) {
+        let mut dispatchers = self.lock().await;
+        dispatchers
+            .channel_watchers
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    pub async fn register_call(
         &self,
-        channel: ChannelID,
-        handler: mpsc::Sender<(ChannelID, Vec<u8>)>,
-    ) {
-        self.lock().await.bin_data_handlers.insert(channel, handler);
+    ) -> Result<(CallID, oneshot::Receiver<RpcResult>), DCClientError> {
+        let (tx, rx) = oneshot::channel();
+        let mut dispatchers = self.lock().await;
+        if dispatchers.closed {
+            return Err(DCClientError::ConnectionBroken);
+        }
+        dispatchers.next_call_id += 1;
+        let call_id = dispatchers.next_call_id;
+        dispatchers.pending_calls.insert(call_id, tx);
+
+        Ok((call_id, rx))
+    }
+
+    pub async fn cancel_call(&self, call_id: CallID) {
+        self.lock().await.pending_calls.remove(&call_id);
     }
 
-    pub async fn dispatch_bin_data(&self, channel: ChannelID, data: Vec<u8>) {
-        if let Some(handler) = self.lock().await.bin_data_handlers.get(&channel) {
-            let _ = handler.send((channel, data)).await;
+    pub async fn dispatch_rpc_response(&self, call_id: CallID, result: RpcResult) {
+        if let Some(tx) = self.lock().await.pending_calls.remove(&call_id) {
+            let _ = tx.send(result);
         } else {
-            warn!("No binary data handler found for channel: {channel}");
+            warn!("No pending call found for RPC response: {call_id}");
         }
     }
 
+    pub async fn register_rpc_handler(&self, channel: ChannelID, handler: RpcHandler) {
+        self.lock().await.rpc_handlers.insert(channel, handler);
+    }
+
+    pub async fn get_rpc_handler(&self, channel: ChannelID) -> Option<RpcHandler> {
+        self.lock().await.rpc_handlers.get(&channel).cloned()
+    }
+
     pub async fn set_node_id(&self, node_id: NodeID) {
         self.lock().await.node_id = Some(node_id);
     }
@@ -200,19 +324,211 @@ impl SharedDispatchers {
     pub async fn get_node_id(&self) -> Option<NodeID> {
         self.lock().await.node_id
     }
+
+    /// Drops every waiter so that pending requests and RPCs fail with `ConnectionBroken`.
+    pub async fn shutdown(&self) {
+        let mut dispatchers = self.lock().await;
+        dispatchers.closed = true;
+        dispatchers.events.clear();
+        dispatchers.resolve_channel.clear();
+        dispatchers.channel_list.clear();
+        dispatchers.channel_info.clear();
+        dispatchers.node_info.clear();
+        dispatchers.supplier_handlers.clear();
+        dispatchers.channel_watchers.clear();
+        dispatchers.pending_calls.clear();
+    }
+}
+
+/// Outcome of a listen request, awaited by every subscriber that joins while it is pending.
+type ListenOutcome = Shared<BoxFuture<'static, Result<(), Arc<DCClientError>>>>;
+
+struct ChannelSubscribers {
+    subscribers: Vec<Subscriber>,
+    /// The listen request sent for the first subscriber.
+    listen: ListenOutcome,
+}
+
+#[derive(Default)]
+struct Subscriptions {
+    channels: HashMap<ChannelID, ChannelSubscribers>,
+    /// Receivers of [`DCClient::directs`].
+    directs: Vec<Subscriber>,
+}
+
+/// Local subscribers of each channel. The server is asked to listen to a channel
+/// while it has at least one subscriber, and messages are fanned out to all of them.
+///
+/// Uses a blocking mutex (never held across `.await`) so that dropping a
+/// [`Subscription`] can unregister synchronously.
+#[derive(Clone, Default)]
+struct SharedSubscriptions(Arc<std::sync::Mutex<Subscriptions>>);
+
+impl SharedSubscriptions {
+    /// Adds a subscriber and returns the outcome of listening to `channel`, calling
+    /// `listen` (under the lock) to send the request if it is the first subscriber.
+    fn add(
+        &self,
+        channel: ChannelID,
+        subscriber: Subscriber,
+        listen: impl FnOnce() -> ListenOutcome,
+    ) -> ListenOutcome {
+        let mut subscriptions = self.0.lock().unwrap();
+        if let Some(entry) = subscriptions.channels.get_mut(&channel) {
+            entry.subscribers.retain(|tx| !tx.is_closed());
+            if !entry.subscribers.is_empty() {
+                entry.subscribers.push(subscriber);
+                return entry.listen.clone();
+            }
+        }
+
+        let listen = listen();
+        let entry = ChannelSubscribers {
+            subscribers: vec![subscriber],
+            listen: listen.clone(),
+        };
+        subscriptions.channels.insert(channel, entry);
+        listen
+    }
+
+    /// Forgets closed subscribers, calling `on_last` (under the lock) if none is left.
+    fn prune(&self, channel: ChannelID, on_last: impl FnOnce()) {
+        let mut subscriptions = self.0.lock().unwrap();
+        let Some(entry) = subscriptions.channels.get_mut(&channel) else {
+            return;
+        };
+        entry.subscribers.retain(|tx| !tx.is_closed());
+        if entry.subscribers.is_empty() {
+            subscriptions.channels.remove(&channel);
+            on_last();
+        }
+    }
+
+    fn add_direct(&self, subscriber: Subscriber) {
+        let mut subscriptions = self.0.lock().unwrap();
+        subscriptions.directs.retain(|tx| !tx.is_closed());
+        subscriptions.directs.push(subscriber);
+    }
+
+    /// Ends every subscription stream.
+    fn clear(&self) {
+        let subscriptions = std::mem::take(&mut *self.0.lock().unwrap());
+        subscriptions
+            .channels
+            .into_values()
+            .flat_map(|entry| entry.subscribers)
+            .chain(subscriptions.directs)
+            .for_each(Subscriber::close);
+    }
+
+    /// Hands `message` to the subscribers of its channel and, if it was sent to
+    /// this node only, to the receivers of [`DCClient::directs`].
+    fn dispatch(&self, message: subscription::Message) {
+        let channel = message.channel();
+        let subscribers: Vec<Subscriber> = {
+            let mut subscriptions = self.0.lock().unwrap();
+            let mut subscribers = subscriptions
+                .channels
+                .get(&channel)
+                .map(|entry| entry.subscribers.clone())
+                .unwrap_or_default();
+            if message.is_direct() {
+                subscriptions.directs.retain(|tx| !tx.is_closed());
+                subscribers.extend(subscriptions.directs.iter().cloned());
+            }
+            subscribers
+        };
+        if subscribers.is_empty() {
+            warn!("No subscriber found for channel: {channel}");
+            return;
+        }
+
+        // each subscriber queues on its own, so a stalled one doesn't hold back the others
+        for subscriber in subscribers {
+            subscriber.deliver(message.clone());
+        }
+    }
+}
+
+/// State of the connection to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// The connection was closed by [`DCClient::close`], the server or an error.
+    Closed,
+}
+
+/// A channel being opened or closed, received through [`DCClient::watch_channels`].
+#[derive(Debug, Clone)]
+pub enum ChannelEvent {
+    Opened(ChannelInfo),
+    /// Carries the info the channel had when it was removed.
+    Closed(ChannelInfo),
+}
+
+/// Supplier state of a persistent channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupplierState {
+    Down,
+    Up(NodeID),
 }
 
 #[derive(Debug)]
 pub enum DCClientError {
-    WSError(tungstenite::Error),
+    /// Could not connect to the server.
+    // boxed to keep `Result<_, DCClientError>` small
+    Connect(Box<tungstenite::Error>),
+    /// The connection failed while writing.
+    WSError(Box<tungstenite::Error>),
+    /// The connection is closed.
     ConnectionBroken,
+    Timeout,
+    /// The server answered with a response that does not match the request.
+    Protocol(String),
+    /// The server refused or could not serve the request.
+    Server(String),
+    /// The RPC handler of the supplier returned an error.
+    RpcError(String),
+    /// The channel was opened for a different payload type.
+    SchemaMismatch {
+        channel: ChannelID,
+        expected: String,
+        actual: String,
+    },
+    Encode(serde_json::Error),
 }
 
 impl std::fmt::Display for DCClientError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            DCClientError::Connect(e) => write!(f, "Failed to connect: {e}"),
             DCClientError::WSError(e) => write!(f, "WebSocket error: {e}"),
             DCClientError::ConnectionBroken => write!(f, "Connection broken"),
+            DCClientError::Timeout => write!(f, "Timed out"),
+            DCClientError::Protocol(e) => write!(f, "Protocol error: {e}"),
+            DCClientError::Server(e) => write!(f, "Server error: {e}"),
+            DCClientError::RpcError(e) => write!(f, "RPC error: {e}"),
+            DCClientError::SchemaMismatch {
+                channel,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Channel {channel} carries {actual}, but {expected} was expected"
+            ),
+            DCClientError::Encode(e) => write!(f, "Failed to encode payload: {e}"),
+        }
+    }
+}
+
+impl DCClientError {
+    /// Copies an error that is reported to several callers. Connection errors,
+    /// which cannot be cloned, become [`DCClientError::ConnectionBroken`].
+    fn shared(&self) -> DCClientError {
+        match self {
+            DCClientError::Server(reason) => DCClientError::Server(reason.clone()),
+            DCClientError::Protocol(reason) => DCClientError::Protocol(reason.clone()),
+            _ => DCClientError::ConnectionBroken,
         }
     }
 }
@@ -220,165 +536,756 @@ impl std::fmt::Display for DCClientError {
 impl std::error::Error for DCClientError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            DCClientError::WSError(e) => Some(e),
-            DCClientError::ConnectionBroken => None,
+            DCClientError::Connect(e) | DCClientError::WSError(e) => Some(e),
+            DCClientError::Encode(e) => Some(e),
+            DCClientError::ConnectionBroken
+            | DCClientError::Timeout
+            | DCClientError::Protocol(_)
+            | DCClientError::Server(_)
+            | DCClientError::RpcError(_)
+            | DCClientError::SchemaMismatch { .. } => None,
         }
     }
 }
 
+/// Handle to a DevConsole connection.
+///
+/// Cloning is cheap and every clone talks over the same connection, so any task
+/// can hold its own handle. The connection is closed when the last handle is dropped.
+#[derive(Clone)]
 pub struct DCClient {
-    tx: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+    writer: mpsc::UnboundedSender<Outgoing>,
+    /// The writer task, aborted when the server does not acknowledge a close.
+    writer_task: AbortHandle,
+    /// Stops the reader task without waiting for the server.
+    stop_reader: Arc<Notify>,
     dispatches: SharedDispatchers,
-    listening_channels: Vec<ChannelID>,
+    subscriptions: SharedSubscriptions,
+    state: watch::Receiver<ConnectionState>,
 }
 
 impl DCClient {
-    pub async fn new(url: &str) -> Result<Self, tokio_tungstenite::tungstenite::Error> {
-        let (ws_stream, _) = connect_async(url).await?;
+    pub async fn new(url: &str) -> Result<Self, DCClientError> {
+        DCClient::new_with_meta(url, NodeMeta::from_env("client")).await
+    }
+
+    pub async fn new_with_meta(url: &str, meta: NodeMeta) -> Result<Self, DCClientError> {
+        let (ws_stream, _) = connect_async(url)
+            .await
+            .map_err(|e| DCClientError::Connect(Box::new(e)))?;
         let (t, r) = ws_stream.split();
+        let events = r
+            .filter_map(|msg| async { msg.ok() })
+            .filter_map(|x| ready(x.to_text().ok().map(|x| x.to_string())))
+            .filter_map(|x| {
+                let event = serde_json::from_str::<Event>(&x)
+                    .inspect_err(|e| warn!("Ignoring malformed message from server: {e}"));
+                ready(event.ok())
+            });
+        let client = DCClient::start(Link::WebSocket(t), events);
+
+        client
+            .send_evt(Event::NodeMetaNotification(meta))
+            .await
+            .map_err(|e| match e {
+                DCClientError::WSError(e) => DCClientError::Connect(e),
+                e => e,
+            })?;
+
+        Ok(client)
+    }
+
+    /// Connects over in-memory channels to a server in the same process, skipping
+    /// TCP and serialization. `to_server` and `from_server` are the client ends of
+    /// the link; see `devconsole_server::connect_local` for the server side.
+    pub async fn new_loopback(
+        meta: NodeMeta,
+        to_server: mpsc::UnboundedSender<Event>,
+        from_server: mpsc::UnboundedReceiver<Event>,
+    ) -> Self {
+        let events = stream::unfold(from_server, |mut rx| async move {
+            rx.recv().await.map(|event| (event, rx))
+        });
+        let client = DCClient::start(Link::Loopback(to_server), events);
+
+        // a failure shows up as a closed connection
+        let _ = client.send_evt(Event::NodeMetaNotification(meta)).await;
+
+        client
+    }
+
+    fn start(link: Link, events: impl Stream<Item = Event> + Send + 'static) -> Self {
+        let (writer, writer_rx) = mpsc::unbounded_channel();
+        let (state_tx, state) = watch::channel(ConnectionState::Connected);
+        let writer_task = tokio::spawn(DCClient::writer_thread(writer_rx, link));
         let client = DCClient {
-            tx: t,
+            writer,
+            writer_task: writer_task.abort_handle(),
+            stop_reader: Arc::new(Notify::new()),
             dispatches: SharedDispatchers::default(),
-            listening_channels: Vec::new(),
+            subscriptions: SharedSubscriptions::default(),
+            state,
         };
 
         let dispatchers = client.dispatches.clone();
+        let subscriptions = client.subscriptions.clone();
+        let writer = client.writer.downgrade();
+        let stop_reader = client.stop_reader.clone();
         tokio::spawn(async move {
-            DCClient::thread(dispatchers, r).await;
+            let events = events.take_until(stop_reader.notified());
+            DCClient::thread(dispatchers, subscriptions, writer, events).await;
+            state_tx.send_replace(ConnectionState::Closed);
         });
 
-        Ok(client)
+        client
     }
 
+    /// Forwards the data of `channel` to the given senders until they are closed.
+    /// Messages that find their sender full are discarded and logged.
     pub async fn listen(
-        &mut self,
+        &self,
         channel: ChannelID,
         channel_tx: Option<mpsc::Sender<(ChannelID, String)>>,
         channel_bin_tx: Option<mpsc::Sender<(ChannelID, Vec<u8>)>>,
     ) -> Result<(), DCClientError> {
-        if self.listening_channels.contains(&channel) {
-            warn!("Channel {channel} is already being listened to");
-            return Ok(());
-        }
-        self.listening_channels.push(channel);
+        let mut subscription = self.subscribe(channel).await?;
 
-        self.send_evt(Event::ChannelListenRequest { channel })
-            .await
-            .map_err(DCClientError::WSError)?;
+        tokio::spawn(async move {
+            let mut dropped = 0u64;
+            while let Some(message) = subscription.next().await {
+                let sent = match message {
+                    subscription::Message::Text { channel, data, .. } => match &channel_tx {
+                        Some(tx) => tx
+                            .try_send((channel, data))
+                            .map_err(|e| matches!(e, TrySendError::Full(_))),
+                        None => Ok(()),
+                    },
+                    subscription::Message::Binary { channel, data, .. } => match &channel_bin_tx {
+                        Some(tx) => tx
+                            .try_send((channel, data))
+                            .map_err(|e| matches!(e, TrySendError::Full(_))),
+                        None => Ok(()),
+                    },
+                };
+                match sent {
+                    Ok(()) => {}
+                    Err(true) => {
+                        dropped += 1;
+                        warn!(
+                            "Listener of channel {channel} is full, dropped {dropped} message(s) so far"
+                        );
+                    }
+                    Err(false) => break,
+                }
+            }
+        });
+
+        Ok(())
+    }
 
-        if let Some(tx) = channel_tx {
-            self.dispatches.register_data_handler(channel, tx).await;
+    /// Listens to `channel` and returns the stream of its messages.
+    ///
+    /// Every subscription of a channel receives all of its messages. Dropping the
+    /// returned [`Subscription`] stops listening once no other subscription is left.
+    pub async fn subscribe(&self, channel: ChannelID) -> Result<Subscription, DCClientError> {
+        self.subscribe_with(channel, Delivery::default()).await
+    }
+
+    /// Like [`DCClient::subscribe`], choosing how messages are buffered for this subscription.
+    pub async fn subscribe_with(
+        &self,
+        channel: ChannelID,
+        delivery: Delivery,
+    ) -> Result<Subscription, DCClientError> {
+        let (tx, rx) = subscription::queue(delivery);
+        let listen = {
+            let mut dispatchers = self.dispatches.lock().await;
+            if dispatchers.closed {
+                return Err(DCClientError::ConnectionBroken);
+            }
+            self.subscriptions.add(channel, tx, || {
+                let (ack, response) = oneshot::channel();
+                dispatchers
+                    .events
+                    .entry(DispatchID::Listen(channel))
+                    .or_default()
+                    .push_back(ack);
+                let sent = self.enqueue(Event::ChannelListenRequest { channel });
+
+                async move {
+                    DCClient::written(sent).await?;
+                    response
+                        .await
+                        .map_err(|_| DCClientError::ConnectionBroken)?
+                }
+                .map(|result| result.map_err(Arc::new))
+                .boxed()
+                .shared()
+            })
+        };
+        let subscription = Subscription::new(channel, rx, self.unsubscriber(channel));
+
+        // a subscriber joining while the listen request of another one is pending
+        // shares its outcome
+        listen.await.map_err(|e| e.shared())?;
+
+        Ok(subscription)
+    }
+
+    /// Returns the stream of the messages other nodes send to this node with
+    /// [`DCClient::send_direct`], whether or not their channel is subscribed.
+    ///
+    /// Direct messages of a subscribed channel also reach its subscriptions.
+    pub async fn directs(&self) -> Result<Directs, DCClientError> {
+        let (tx, rx) = subscription::queue(Delivery::default());
+        {
+            let dispatchers = self.dispatches.lock().await;
+            if dispatchers.closed {
+                return Err(DCClientError::ConnectionBroken);
+            }
+            self.subscriptions.add_direct(tx);
         }
 
-        if let Some(bin_tx) = channel_bin_tx {
-            self.dispatches
-                .register_bin_data_handler(channel, bin_tx)
-                .await;
+        Ok(Directs::new(rx))
+    }
+
+    /// Subscribes to a typed channel and decodes its payloads as `T`.
+    ///
+    /// Fails with [`DCClientError::SchemaMismatch`] if the channel was opened for
+    /// another type. Channels opened without a schema are accepted as is.
+    pub async fn subscribe_typed<T: Schema + DeserializeOwned>(
+        &self,
+        channel: ChannelID,
+    ) -> Result<TypedSubscription<T>, DCClientError> {
+        let info = self.channel_info(channel).await?;
+        let expected = T::NAME.to_string();
+        if let Some(actual) = info.schema
+            && actual != expected
+        {
+            return Err(DCClientError::SchemaMismatch {
+                channel,
+                expected,
+                actual,
+            });
         }
 
-        let response = self
-            .dispatches
-            .wait_for_event(DispatchID::Listen(channel))
-            .await;
+        let subscription = self.subscribe(channel).await?;
+        Ok(TypedSubscription::new(subscription))
+    }
 
-        if !response {
-            Err(DCClientError::ConnectionBroken)
-        } else {
-            Ok(())
+    /// Subscribes to the binary data of `channel` as an `AsyncRead + AsyncWrite` byte stream.
+    pub async fn channel_stream(&self, channel: ChannelID) -> Result<ChannelStream, DCClientError> {
+        let subscription = self.subscribe(channel).await?;
+        Ok(ChannelStream::new(self.clone(), subscription))
+    }
+
+    fn unsubscriber(&self, channel: ChannelID) -> impl FnOnce() + Send + 'static {
+        let subscriptions = self.subscriptions.clone();
+        let writer = self.writer.downgrade();
+        move || {
+            subscriptions.prune(channel, || {
+                if let Some(writer) = writer.upgrade() {
+                    let (sent, _) = oneshot::channel();
+                    let event = Event::ChannelUnlistenRequest { channel };
+                    let _ = writer.send(Outgoing::Event { event, sent });
+                }
+            });
         }
     }
 
-    pub async fn send(&mut self, channel: ChannelID, data: String) -> Result<(), DCClientError> {
-        self.send_evt(Event::Data { channel, data })
-            .await
-            .map_err(DCClientError::WSError)
+    pub async fn send(&self, channel: ChannelID, data: String) -> Result<(), DCClientError> {
+        self.send_evt(Event::Data {
+            channel,
+            from: 0,
+            data,
+        })
+        .await
+    }
+
+    pub async fn send_bin(&self, channel: ChannelID, data: Vec<u8>) -> Result<(), DCClientError> {
+        self.send_evt(Event::DataBin {
+            channel,
+            from: 0,
+            data,
+        })
+        .await
+    }
+
+    /// Sends data on `channel` to the node `to` only.
+    pub async fn send_direct(
+        &self,
+        to: NodeID,
+        channel: ChannelID,
+        data: String,
+    ) -> Result<(), DCClientError> {
+        self.send_evt(Event::Direct {
+            to,
+            from: 0,
+            channel,
+            data,
+        })
+        .await
     }
 
-    pub async fn send_bin(
-        &mut self,
+    pub async fn send_direct_bin(
+        &self,
+        to: NodeID,
         channel: ChannelID,
         data: Vec<u8>,
     ) -> Result<(), DCClientError> {
-        self.send_evt(Event::DataBin { channel, data })
+        self.send_evt(Event::DirectBin {
+            to,
+            from: 0,
+            channel,
+            data,
+        })
+        .await
+    }
+
+    pub async fn open(&self, name: String) -> Result<ChannelID, DCClientError> {
+        self.open_channel(name, false, None).await
+    }
+
+    /// Opens a channel that survives this client's disconnection for a grace
+    /// period. Re-opening the same name re-binds the existing channel ID.
+    pub async fn open_persistent(&self, name: String) -> Result<ChannelID, DCClientError> {
+        self.open_channel(name, true, None).await
+    }
+
+    /// Opens a channel carrying `T` payloads, recording its schema in the `ChannelInfo`.
+    pub async fn open_typed<T: Schema>(&self, name: String) -> Result<ChannelID, DCClientError> {
+        self.open_channel(name, false, Some(T::NAME.to_string()))
             .await
-            .map_err(DCClientError::WSError)
     }
 
-    pub async fn open(&mut self, name: String) -> Result<ChannelID, DCClientError> {
-        self.send_evt(Event::ChannelOpenRequest { name })
+    pub async fn open_persistent_typed<T: Schema>(
+        &self,
+        name: String,
+    ) -> Result<ChannelID, DCClientError> {
+        self.open_channel(name, true, Some(T::NAME.to_string()))
             .await
-            .map_err(DCClientError::WSError)?;
+    }
 
-        let channel = self.dispatches.wait_for_channel().await;
-        Ok(channel)
+    async fn open_channel(
+        &self,
+        name: String,
+        persistent: bool,
+        schema: Option<String>,
+    ) -> Result<ChannelID, DCClientError> {
+        let request = Event::ChannelOpenRequest {
+            name,
+            persistent,
+            schema,
+        };
+        self.request(request, |d| &mut d.resolve_channel).await
     }
 
-    pub async fn channel_list(&mut self) -> Result<Vec<ChannelID>, DCClientError> {
-        self.send_evt(Event::ChannelListRequest)
-            .await
-            .map_err(DCClientError::WSError)?;
+    /// Sends `payload` as JSON text, to be received with [`DCClient::subscribe_typed`].
+    pub async fn send_typed<T: Serialize>(
+        &self,
+        channel: ChannelID,
+        payload: &T,
+    ) -> Result<(), DCClientError> {
+        let data = serde_json::to_string(payload).map_err(DCClientError::Encode)?;
+        self.send(channel, data).await
+    }
 
-        let channels = self.dispatches.wait_for_channel_list().await;
-        Ok(channels)
+    /// Receives supplier down/up notifications of a listened persistent channel.
+    pub async fn watch_supplier(
+        &self,
+        channel: ChannelID,
+        tx: mpsc::Sender<(ChannelID, SupplierState)>,
+    ) {
+        self.dispatches.register_supplier_handler(channel, tx).await;
     }
-    pub async fn channel_info(&mut self, channel: ChannelID) -> Result<ChannelInfo, DCClientError> {
-        self.send_evt(Event::ChannelInfoRequest(channel))
+
+    /// Receives the channels opened and closed from now on, until the connection ends.
+    pub async fn watch_channels(
+        &self,
+        tx: mpsc::UnboundedSender<ChannelEvent>,
+    ) -> Result<(), DCClientError> {
+        self.dispatches.lock().await.channel_watchers.push(tx);
+        self.request(Event::ChannelWatchRequest, |d| {
+            d.events.entry(DispatchID::ChannelWatch).or_default()
+        })
+        .await
+    }
+
+    pub async fn channel_list(&self) -> Result<Vec<ChannelID>, DCClientError> {
+        self.request(Event::ChannelListRequest, |d| &mut d.channel_list)
             .await
-            .map_err(DCClientError::WSError)?;
+    }
+
+    pub async fn channel_info(&self, channel: ChannelID) -> Result<ChannelInfo, DCClientError> {
+        let info = self
+            .request(Event::ChannelInfoRequest(channel), |d| &mut d.channel_info)
+            .await?;
+        if info.channel != channel {
+            return Err(DCClientError::Protocol(format!(
+                "Requested info of channel {channel}, got channel {}",
+                info.channel
+            )));
+        }
 
-        let info = self.dispatches.wait_for_channel_info().await;
         Ok(info)
     }
 
+    pub async fn node_info(&self, node_id: NodeID) -> Result<NodeInfo, DCClientError> {
+        let info = self
+            .request(Event::NodeInfoRequest(node_id), |d| &mut d.node_info)
+            .await?;
+        if info.node_id != node_id {
+            return Err(DCClientError::Protocol(format!(
+                "Requested info of node {node_id}, got node {}",
+                info.node_id
+            )));
+        }
+
+        Ok(info)
+    }
+
+    /// Sends a request to the supplier of `channel` and waits for its reply.
+    pub async fn call(
+        &self,
+        channel: ChannelID,
+        data: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, DCClientError> {
+        let (call_id, response) = self.dispatches.register_call().await?;
+
+        let sent = self
+            .send_evt(Event::RpcRequest {
+                channel,
+                call_id,
+                data,
+                timeout_ms: Some(timeout.as_millis() as u64),
+            })
+            .await;
+        if let Err(e) = sent {
+            self.dispatches.cancel_call(call_id).await;
+            return Err(e);
+        }
+
+        match tokio::time::timeout(timeout, response).await {
+            Ok(Ok(result)) => result.map_err(DCClientError::RpcError),
+            Ok(Err(_)) => Err(DCClientError::ConnectionBroken),
+            Err(_) => {
+                self.dispatches.cancel_call(call_id).await;
+                Err(DCClientError::Timeout)
+            }
+        }
+    }
+
+    /// Answers RPC requests sent to `channel` (which must be supplied by this client).
+    pub async fn serve<F, Fut>(&self, channel: ChannelID, handler: F)
+    where
+        F: Fn(Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Vec<u8>, String>> + Send + 'static,
+    {
+        let handler: RpcHandler = Arc::new(move |data| Box::pin(handler(data)));
+        self.dispatches.register_rpc_handler(channel, handler).await;
+    }
+
     pub async fn get_node_id(&self) -> Option<NodeID> {
         self.dispatches.get_node_id().await
     }
 
-    async fn send_evt(
-        &mut self,
+    /// Watches the connection state.
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        *self.state.borrow() == ConnectionState::Connected
+    }
+
+    /// Resolves once the connection is closed.
+    pub async fn closed(&self) {
+        let mut state = self.state.clone();
+        let _ = state.wait_for(|s| *s == ConnectionState::Closed).await;
+    }
+
+    /// Closes the connection for every handle and waits for the server to acknowledge it,
+    /// for up to 5 seconds.
+    ///
+    /// Pending requests and RPCs fail with [`DCClientError::ConnectionBroken`] and
+    /// subscription streams end.
+    pub async fn close(&self) {
+        self.close_timeout(CLOSE_TIMEOUT).await;
+    }
+
+    /// Like [`DCClient::close`], waiting up to `timeout` for the server. After that the
+    /// connection is dropped without completing the close handshake.
+    pub async fn close_timeout(&self, timeout: Duration) {
+        let _ = self.writer.send(Outgoing::Close);
+        if tokio::time::timeout(timeout, self.closed()).await.is_ok() {
+            return;
+        }
+
+        warn!(
+            "The server did not acknowledge the close within {timeout:?}, dropping the connection"
+        );
+        self.writer_task.abort();
+        self.stop_reader.notify_one();
+        self.closed().await;
+    }
+
+    /// Queues an event for the writer task without waiting for it to be written.
+    pub(crate) fn enqueue(&self, event: Event) -> oneshot::Receiver<WriteResult> {
+        let (sent, written) = oneshot::channel();
+        // if the writer task is gone, `sent` is dropped and `written` fails
+        let _ = self.writer.send(Outgoing::Event { event, sent });
+        written
+    }
+
+    async fn written(written: oneshot::Receiver<WriteResult>) -> Result<(), DCClientError> {
+        match written.await {
+            Ok(result) => result.map_err(|e| DCClientError::WSError(Box::new(e))),
+            Err(_) => Err(DCClientError::ConnectionBroken),
+        }
+    }
+
+    async fn send_evt(&self, event: Event) -> Result<(), DCClientError> {
+        DCClient::written(self.enqueue(event)).await
+    }
+
+    /// Sends a request and waits for the response that is dispatched to `waiters`.
+    async fn request<T>(
+        &self,
         event: Event,
-    ) -> Result<(), tokio_tungstenite::tungstenite::Error> {
-        let msg = serde_json::to_string(&event).unwrap();
-        self.tx.send(Message::Text(msg.into())).await?;
-        Ok(())
+        waiters: impl FnOnce(&mut Dispatchers) -> &mut VecDeque<Waiter<T>>,
+    ) -> Result<T, DCClientError> {
+        let (tx, response) = oneshot::channel();
+        // register and queue under the same lock so that concurrent requests
+        // are written in the same order as their waiters
+        let sent = {
+            let mut dispatchers = self.dispatches.lock().await;
+            if dispatchers.closed {
+                return Err(DCClientError::ConnectionBroken);
+            }
+            waiters(&mut dispatchers).push_back(tx);
+            self.enqueue(event)
+        };
+        DCClient::written(sent).await?;
+
+        response
+            .await
+            .map_err(|_| DCClientError::ConnectionBroken)?
+    }
+
+    async fn writer_thread(mut rx: mpsc::UnboundedReceiver<Outgoing>, mut link: Link) {
+        // runs until `close()` is called or every handle has been dropped
+        while let Some(Outgoing::Event { event, sent }) = rx.recv().await {
+            let _ = sent.send(link.send(event).await);
+        }
+
+        link.close().await;
+    }
+
+    async fn handle_rpc_request(
+        dispatchers: &SharedDispatchers,
+        writer: &mpsc::WeakUnboundedSender<Outgoing>,
+        channel: ChannelID,
+        call_id: CallID,
+        data: Vec<u8>,
+    ) {
+        let handler = dispatchers.get_rpc_handler(channel).await;
+        let Some(writer) = writer.upgrade() else {
+            return;
+        };
+        tokio::spawn(async move {
+            let result = match handler {
+                Some(handler) => handler(data).await,
+                None => Err(format!("No RPC handler for channel {channel}")),
+            };
+
+            let (sent, _) = oneshot::channel();
+            let response = Event::RpcResponse { call_id, result };
+            if writer
+                .send(Outgoing::Event {
+                    event: response,
+                    sent,
+                })
+                .is_err()
+            {
+                warn!("Failed to send RPC response: connection closed");
+            }
+        });
+    }
+
+    /// Hands data and supplier notifications to their consumers, so that a slow
+    /// consumer doesn't stall the reader task. Subscriptions never make this wait:
+    /// one that falls too far behind loses messages instead.
+    async fn delivery_thread(
+        dispatchers: SharedDispatchers,
+        subscriptions: SharedSubscriptions,
+        mut inbound: mpsc::Receiver<Inbound>,
+    ) {
+        while let Some(item) = inbound.recv().await {
+            match item {
+                Inbound::Message(message) => subscriptions.dispatch(message),
+                Inbound::SupplierState(channel, state) => {
+                    dispatchers.dispatch_supplier_state(channel, state).await;
+                }
+                Inbound::Channel(event) => dispatchers.dispatch_channel_event(event).await,
+            }
+        }
+
+        subscriptions.clear();
     }
 
     async fn thread(
         dispatchers: SharedDispatchers,
-        r: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+        subscriptions: SharedSubscriptions,
+        writer: mpsc::WeakUnboundedSender<Outgoing>,
+        events: impl Stream<Item = Event>,
     ) {
-        r.filter_map(|msg| async { msg.ok() })
-            .filter_map(|x| ready(x.to_text().ok().map(|x| x.to_string())))
-            .filter_map(|x| ready(serde_json::from_str::<Event>(&x).ok()))
+        let (inbound, inbound_rx) = mpsc::channel(INBOUND_BUFFER);
+        tokio::spawn(DCClient::delivery_thread(
+            dispatchers.clone(),
+            subscriptions,
+            inbound_rx,
+        ));
+
+        events
             .for_each(|event| async {
                 match event {
                     Event::NodeIDNotification { node_id } => {
                         info!("Node ID: {node_id}");
                         dispatchers.set_node_id(node_id).await;
                     }
-                    Event::Data { channel, data } => {
-                        dispatchers.dispatch_data(channel, data).await;
+                    Event::Data {
+                        channel,
+                        from,
+                        data,
+                    } => {
+                        let message = subscription::Message::Text {
+                            channel,
+                            from,
+                            direct: false,
+                            data,
+                        };
+                        let _ = inbound.send(Inbound::Message(message)).await;
+                    }
+                    Event::DataBin {
+                        channel,
+                        from,
+                        data,
+                    } => {
+                        let message = subscription::Message::Binary {
+                            channel,
+                            from,
+                            direct: false,
+                            data,
+                        };
+                        let _ = inbound.send(Inbound::Message(message)).await;
                     }
-                    Event::DataBin { channel, data } => {
-                        dispatchers.dispatch_bin_data(channel, data).await;
+                    Event::Direct {
+                        channel,
+                        from,
+                        data,
+                        ..
+                    } => {
+                        let message = subscription::Message::Text {
+                            channel,
+                            from,
+                            direct: true,
+                            data,
+                        };
+                        let _ = inbound.send(Inbound::Message(message)).await;
                     }
-                    Event::ChannelOpenResponse {
+                    Event::DirectBin {
                         channel,
-                        success: _,
+                        from,
+                        data,
+                        ..
                     } => {
-                        dispatchers.dispatch_channel(channel).await;
+                        let message = subscription::Message::Binary {
+                            channel,
+                            from,
+                            direct: true,
+                            data,
+                        };
+                        let _ = inbound.send(Inbound::Message(message)).await;
+                    }
+                    Event::ChannelOpenResponse { channel, success } => {
+                        let result = if success {
+                            Ok(channel)
+                        } else {
+                            Err(DCClientError::Server(
+                                "Channel open was refused".to_string(),
+                            ))
+                        };
+                        dispatchers.dispatch_channel(result).await;
+                    }
+                    Event::ChannelSupplierDown { channel } => {
+                        let _ = inbound
+                            .send(Inbound::SupplierState(channel, SupplierState::Down))
+                            .await;
+                    }
+                    Event::ChannelSupplierUp {
+                        channel,
+                        supplied_by,
+                    } => {
+                        let state = SupplierState::Up(supplied_by);
+                        let _ = inbound.send(Inbound::SupplierState(channel, state)).await;
                     }
                     Event::ChannelListenResponse { channel, success } => {
+                        let result = if success {
+                            Ok(())
+                        } else {
+                            Err(DCClientError::Server(format!(
+                                "Listening to channel {channel} was refused"
+                            )))
+                        };
                         dispatchers
-                            .dispatch_event(DispatchID::Listen(channel), success)
+                            .dispatch_event(DispatchID::Listen(channel), result)
                             .await;
                     }
+                    Event::ChannelWatchResponse { success } => {
+                        let result = if success {
+                            Ok(())
+                        } else {
+                            Err(DCClientError::Server(
+                                "Watching channels was refused".to_string(),
+                            ))
+                        };
+                        dispatchers
+                            .dispatch_event(DispatchID::ChannelWatch, result)
+                            .await;
+                    }
+                    Event::ChannelOpened(info) => {
+                        let event = ChannelEvent::Opened(info);
+                        let _ = inbound.send(Inbound::Channel(event)).await;
+                    }
+                    Event::ChannelClosed(info) => {
+                        let event = ChannelEvent::Closed(info);
+                        let _ = inbound.send(Inbound::Channel(event)).await;
+                    }
                     Event::ChannelListResponse { channels } => {
-                        dispatchers.dispatch_channel_list(channels).await;
+                        dispatchers.dispatch_channel_list(Ok(channels)).await;
                     }
 
                     Event::ChannelInfoResponse(info) => {
-                        dispatchers.dispatch_channel_info(info).await;
+                        dispatchers.dispatch_channel_info(Ok(info)).await;
+                    }
+                    Event::NodeInfoResponse(info) => {
+                        dispatchers.dispatch_node_info(Ok(info)).await;
+                    }
+                    Event::RequestFailed { request, reason } => {
+                        dispatchers.dispatch_failure(&request, reason).await;
+                    }
+                    Event::RpcRequest {
+                        channel,
+                        call_id,
+                        data,
+                        ..
+                    } => {
+                        DCClient::handle_rpc_request(&dispatchers, &writer, channel, call_id, data)
+                            .await;
+                    }
+                    Event::RpcResponse { call_id, result } => {
+                        dispatchers.dispatch_rpc_response(call_id, result).await;
                     }
                     _ => {
                         warn!("Unhandled event: {event:?}");
@@ -386,5 +1293,13 @@ impl DCClient {
                 }
             })
             .await;
+
+        info!("Connection closed");
+        dispatchers.shutdown().await;
+        // the delivery task ends the subscriptions once it has handed out what is left
+        drop(inbound);
+        if let Some(writer) = writer.upgrade() {
+            let _ = writer.send(Outgoing::Close);
+        }
     }
 }
@@ -1,5 +1,10 @@
+pub mod blocking;
 mod client;
 mod protocol;
+mod subscription;
 
-pub use client::DCClient;
+pub use client::{ChannelEvent, ConnectionState, DCClient, DCClientError, SupplierState};
 pub use protocol::*;
+pub use subscription::{
+    ChannelStream, Delivery, Directs, Message, Subscription, TypedSubscription,
+};
@@ -2,36 +2,210 @@ use serde::{Deserialize, Serialize};
 
 pub type ChannelID = u64;
 pub type NodeID = u64;
+pub type CallID = u64;
 
 pub enum TransactionError {
     ChannelConflicted,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Self-declared description of a node, sent by the client right after connecting.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeMeta {
+    pub name: String,
+    pub kind: String,
+    pub hostname: String,
+    pub pid: u32,
+}
+
+impl NodeMeta {
+    pub fn new(name: &str, kind: &str) -> Self {
+        NodeMeta {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            hostname: hostname::get()
+                .map(|h| h.to_string_lossy().to_string())
+                .unwrap_or_else(|_| "unknown".to_string()),
+            pid: std::process::id(),
+        }
+    }
+
+    /// Uses the executable name as the node name.
+    pub fn from_env(kind: &str) -> Self {
+        let name = std::env::current_exe()
+            .ok()
+            .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().to_string()))
+            .unwrap_or_else(|| "unknown".to_string());
+        NodeMeta::new(&name, kind)
+    }
+}
+
+impl std::fmt::Display for NodeMeta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.name, self.hostname)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub node_id: NodeID,
+    /// `None` if the node is unknown or has not declared itself
+    pub meta: Option<NodeMeta>,
+}
+
+impl std::fmt::Display for NodeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.meta {
+            Some(meta) => write!(f, "{meta} ({})", self.node_id),
+            None => write!(f, "{}", self.node_id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChannelInfo {
     pub channel: ChannelID,
     pub name: String,
     pub supplied_by: NodeID,
+    #[serde(default)]
+    pub supplier: Option<NodeMeta>,
+    /// Name of the payload type of a typed channel (see [`Schema`]).
+    #[serde(default)]
+    pub schema: Option<String>,
+}
+
+impl ChannelInfo {
+    pub fn supplier_info(&self) -> NodeInfo {
+        NodeInfo {
+            node_id: self.supplied_by,
+            meta: self.supplier.clone(),
+        }
+    }
+}
+
+/// Payload type of a typed channel.
+///
+/// `NAME` is recorded as the schema of the channel. It is spelled out rather than
+/// derived from the type so that nodes built separately, by other compilers or
+/// with the type at another path, agree on it.
+pub trait Schema {
+    const NAME: &'static str;
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Event {
-    NodeIDNotification { node_id: NodeID },
+    NodeIDNotification {
+        node_id: NodeID,
+    },
+    NodeMetaNotification(NodeMeta),
 
-    Data { channel: ChannelID, data: String },
-    DataBin { channel: ChannelID, data: Vec<u8> },
+    NodeInfoRequest(NodeID),
+    NodeInfoResponse(NodeInfo),
 
-    ChannelOpenRequest { name: String },
-    ChannelOpenResponse { channel: ChannelID, success: bool },
+    /// Data broadcast to the listeners of `channel`. `from` is filled in by the server.
+    Data {
+        channel: ChannelID,
+        #[serde(default)]
+        from: NodeID,
+        data: String,
+    },
+    DataBin {
+        channel: ChannelID,
+        #[serde(default)]
+        from: NodeID,
+        data: Vec<u8>,
+    },
+    /// Data delivered to the node `to` only. `from` is filled in by the server.
+    Direct {
+        to: NodeID,
+        #[serde(default)]
+        from: NodeID,
+        channel: ChannelID,
+        data: String,
+    },
+    DirectBin {
+        to: NodeID,
+        #[serde(default)]
+        from: NodeID,
+        channel: ChannelID,
+        data: Vec<u8>,
+    },
 
-    ChannelCloseRequest { channel: ChannelID },
+    ChannelOpenRequest {
+        name: String,
+        /// Keep the channel (and its listeners) alive for a grace period after
+        /// the supplier disconnects, and re-bind it when a node opens the same name.
+        #[serde(default)]
+        persistent: bool,
+        /// Payload type of a typed channel, recorded in its `ChannelInfo`.
+        #[serde(default)]
+        schema: Option<String>,
+    },
+    ChannelOpenResponse {
+        channel: ChannelID,
+        success: bool,
+    },
 
-    ChannelListenRequest { channel: ChannelID },
-    ChannelListenResponse { channel: ChannelID, success: bool },
+    ChannelSupplierDown {
+        channel: ChannelID,
+    },
+    ChannelSupplierUp {
+        channel: ChannelID,
+        supplied_by: NodeID,
+    },
+
+    ChannelCloseRequest {
+        channel: ChannelID,
+    },
+
+    /// Asks to be told about channels being opened and closed until the connection ends.
+    ChannelWatchRequest,
+    ChannelWatchResponse {
+        success: bool,
+    },
+    /// A new channel, sent to the nodes that sent `ChannelWatchRequest`. A persistent
+    /// channel re-bound to a new supplier keeps its ID and is not reported again.
+    ChannelOpened(ChannelInfo),
+    /// A channel that was removed, with the info it had.
+    ChannelClosed(ChannelInfo),
+
+    ChannelListenRequest {
+        channel: ChannelID,
+    },
+    ChannelListenResponse {
+        channel: ChannelID,
+        success: bool,
+    },
+    ChannelUnlistenRequest {
+        channel: ChannelID,
+    },
 
     ChannelListRequest,
-    ChannelListResponse { channels: Vec<ChannelID> },
+    ChannelListResponse {
+        channels: Vec<ChannelID>,
+    },
 
     ChannelInfoRequest(ChannelID),
     ChannelInfoResponse(ChannelInfo),
+
+    /// Caller -> server -> supplier of `channel`. The server rewrites `call_id`.
+    RpcRequest {
+        channel: ChannelID,
+        call_id: CallID,
+        data: Vec<u8>,
+        /// How long the caller waits for the response, in milliseconds. The server
+        /// forgets the call afterwards.
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    /// Supplier -> server -> caller only.
+    RpcResponse {
+        call_id: CallID,
+        result: Result<Vec<u8>, String>,
+    },
+
+    /// Sent by the server instead of the response to `request` when it cannot be served.
+    RequestFailed {
+        request: Box<Event>,
+        reason: String,
+    },
 }
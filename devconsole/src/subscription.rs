@@ -0,0 +1,536 @@
+use std::{
+    collections::VecDeque,
+    future::poll_fn,
+    io,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll, Waker, ready},
+};
+
+use futures_util::Stream;
+use serde::de::DeserializeOwned;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::{mpsc, oneshot},
+};
+
+use crate::{ChannelID, DCClient, Event, NodeID, client::WriteResult};
+
+/// Data received on a subscribed channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text {
+        channel: ChannelID,
+        from: NodeID,
+        /// Sent to this node only (`send_direct`) rather than broadcast.
+        direct: bool,
+        data: String,
+    },
+    Binary {
+        channel: ChannelID,
+        from: NodeID,
+        direct: bool,
+        data: Vec<u8>,
+    },
+}
+
+impl Message {
+    pub fn channel(&self) -> ChannelID {
+        match self {
+            Message::Text { channel, .. } | Message::Binary { channel, .. } => *channel,
+        }
+    }
+
+    pub fn from(&self) -> NodeID {
+        match self {
+            Message::Text { from, .. } | Message::Binary { from, .. } => *from,
+        }
+    }
+
+    pub fn is_direct(&self) -> bool {
+        match self {
+            Message::Text { direct, .. } | Message::Binary { direct, .. } => *direct,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Message::Text { data, .. } => data.as_bytes(),
+            Message::Binary { data, .. } => data,
+        }
+    }
+
+    /// Decodes the JSON payload sent by [`DCClient::send_typed`].
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(self.as_bytes())
+    }
+}
+
+// Messages buffered per subscription by default before the delivery task waits.
+const DEFAULT_BUFFER: usize = 64;
+
+// Messages held for a full `Block` subscription before new ones are discarded.
+const BLOCK_BACKLOG: usize = 1024;
+
+/// What happens when a subscription receives messages faster than they are read.
+///
+/// Messages are handed to subscriptions by a task separate from the one reading
+/// the connection, so a slow subscriber never delays responses to requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Buffer up to `n` messages, then hold the following ones back until the
+    /// subscriber catches up. Only this subscription waits; once it is more than
+    /// `BLOCK_BACKLOG` (1024) messages behind, new messages are discarded (see
+    /// [`Subscription::dropped`]) until there is room again.
+    Block(usize),
+    /// Buffer up to `n` messages, discarding the oldest one to make room.
+    DropOldest(usize),
+    /// Buffer without limit.
+    Unbounded,
+}
+
+impl Default for Delivery {
+    fn default() -> Self {
+        Delivery::Block(DEFAULT_BUFFER)
+    }
+}
+
+/// Creates the queue between the delivery task and a subscription.
+pub(crate) fn queue(delivery: Delivery) -> (Subscriber, Receiver) {
+    match delivery {
+        Delivery::Block(n) => {
+            let (tx, rx) = mpsc::channel(n.max(1));
+            let (backlog, backlog_rx) = mpsc::channel(BLOCK_BACKLOG);
+            let dropped = Arc::new(AtomicU64::new(0));
+            tokio::spawn(forward(backlog_rx, tx.clone()));
+            (
+                Subscriber::Block {
+                    backlog,
+                    queue: tx,
+                    dropped: dropped.clone(),
+                },
+                Receiver::Block(rx, dropped),
+            )
+        }
+        Delivery::DropOldest(n) => {
+            let ring = Arc::new(Ring::new(n.max(1)));
+            (
+                Subscriber::DropOldest(ring.clone()),
+                Receiver::DropOldest(ring),
+            )
+        }
+        Delivery::Unbounded => {
+            let (tx, rx) = mpsc::unbounded_channel();
+            (Subscriber::Unbounded(tx), Receiver::Unbounded(rx))
+        }
+    }
+}
+
+/// Moves the backlog of a [`Delivery::Block`] subscription into its queue as room
+/// frees up, so that only this task waits for the subscriber.
+async fn forward(mut backlog: mpsc::Receiver<Message>, queue: mpsc::Sender<Message>) {
+    loop {
+        let message = tokio::select! {
+            message = backlog.recv() => message,
+            () = queue.closed() => None,
+        };
+        let Some(message) = message else {
+            break;
+        };
+        if queue.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Sending half of a subscription queue, held by the client.
+#[derive(Clone)]
+pub(crate) enum Subscriber {
+    Block {
+        /// Feeds the subscription through [`forward`].
+        backlog: mpsc::Sender<Message>,
+        /// Only used to tell whether the subscription is gone.
+        queue: mpsc::Sender<Message>,
+        /// Messages discarded because the backlog was full.
+        dropped: Arc<AtomicU64>,
+    },
+    DropOldest(Arc<Ring>),
+    Unbounded(mpsc::UnboundedSender<Message>),
+}
+
+impl Subscriber {
+    /// Hands `message` to the subscription without waiting, discarding it if the
+    /// subscription is a `Block` one and its backlog is full.
+    pub(crate) fn deliver(&self, message: Message) {
+        match self {
+            Subscriber::Block {
+                backlog, dropped, ..
+            } => match backlog.try_send(message) {
+                Ok(()) | Err(mpsc::error::TrySendError::Closed(_)) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            },
+            Subscriber::DropOldest(ring) => ring.push(message),
+            Subscriber::Unbounded(tx) => {
+                let _ = tx.send(message);
+            }
+        }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        match self {
+            Subscriber::Block { queue, .. } => queue.is_closed(),
+            Subscriber::DropOldest(ring) => ring.is_closed(),
+            Subscriber::Unbounded(tx) => tx.is_closed(),
+        }
+    }
+
+    /// Ends the subscription stream once the buffered messages are read.
+    pub(crate) fn close(self) {
+        // the mpsc receivers see the end once every sender is dropped
+        if let Subscriber::DropOldest(ring) = self {
+            ring.close();
+        }
+    }
+}
+
+pub(crate) enum Receiver {
+    Block(mpsc::Receiver<Message>, Arc<AtomicU64>),
+    DropOldest(Arc<Ring>),
+    Unbounded(mpsc::UnboundedReceiver<Message>),
+}
+
+impl Receiver {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        match self {
+            Receiver::Block(rx, _) => rx.poll_recv(cx),
+            Receiver::DropOldest(ring) => ring.poll_pop(cx),
+            Receiver::Unbounded(rx) => rx.poll_recv(cx),
+        }
+    }
+
+    fn close(&mut self) {
+        match self {
+            Receiver::Block(rx, _) => rx.close(),
+            Receiver::DropOldest(ring) => ring.close(),
+            Receiver::Unbounded(rx) => rx.close(),
+        }
+    }
+
+    fn dropped(&self) -> u64 {
+        match self {
+            Receiver::Block(_, dropped) => dropped.load(Ordering::Relaxed),
+            Receiver::DropOldest(ring) => ring.state().dropped,
+            Receiver::Unbounded(_) => 0,
+        }
+    }
+}
+
+/// Bounded queue of a [`Delivery::DropOldest`] subscription.
+pub(crate) struct Ring(Mutex<RingState>);
+
+struct RingState {
+    messages: VecDeque<Message>,
+    capacity: usize,
+    dropped: u64,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl Ring {
+    fn new(capacity: usize) -> Self {
+        Ring(Mutex::new(RingState {
+            messages: VecDeque::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+            closed: false,
+            waker: None,
+        }))
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, RingState> {
+        self.0.lock().unwrap()
+    }
+
+    fn push(&self, message: Message) {
+        let mut ring = self.state();
+        if ring.closed {
+            return;
+        }
+        if ring.messages.len() >= ring.capacity {
+            ring.messages.pop_front();
+            ring.dropped += 1;
+        }
+        ring.messages.push_back(message);
+        if let Some(waker) = ring.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn close(&self) {
+        let mut ring = self.state();
+        ring.closed = true;
+        if let Some(waker) = ring.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state().closed
+    }
+
+    fn poll_pop(&self, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        let mut ring = self.state();
+        if let Some(message) = ring.messages.pop_front() {
+            return Poll::Ready(Some(message));
+        }
+        if ring.closed {
+            return Poll::Ready(None);
+        }
+        ring.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Stream of the messages of a channel, returned by [`DCClient::subscribe`].
+///
+/// The channel is unlistened when the subscription is dropped.
+pub struct Subscription {
+    channel: ChannelID,
+    rx: Receiver,
+    unsubscribe: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Subscription {
+    pub(crate) fn new(
+        channel: ChannelID,
+        rx: Receiver,
+        unsubscribe: impl FnOnce() + Send + 'static,
+    ) -> Self {
+        Subscription {
+            channel,
+            rx,
+            unsubscribe: Some(Box::new(unsubscribe)),
+        }
+    }
+
+    pub fn channel(&self) -> ChannelID {
+        self.channel
+    }
+
+    /// Receives the next message, or `None` once the connection is gone.
+    pub async fn recv(&mut self) -> Option<Message> {
+        poll_fn(|cx| self.rx.poll_recv(cx)).await
+    }
+
+    /// Number of messages discarded so far because the subscription fell behind
+    /// (never with [`Delivery::Unbounded`]).
+    pub fn dropped(&self) -> u64 {
+        self.rx.dropped()
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.rx.close();
+        if let Some(unsubscribe) = self.unsubscribe.take() {
+            unsubscribe();
+        }
+    }
+}
+
+/// Stream of the messages sent to this node only, returned by [`DCClient::directs`].
+///
+/// Every message carries its channel and the node that sent it.
+pub struct Directs {
+    rx: Receiver,
+}
+
+impl Directs {
+    pub(crate) fn new(rx: Receiver) -> Self {
+        Directs { rx }
+    }
+
+    /// Receives the next direct message, or `None` once the connection is gone.
+    pub async fn recv(&mut self) -> Option<Message> {
+        poll_fn(|cx| self.rx.poll_recv(cx)).await
+    }
+}
+
+impl Stream for Directs {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for Directs {
+    fn drop(&mut self) {
+        self.rx.close();
+    }
+}
+
+/// Stream of the decoded payloads of a typed channel, returned by [`DCClient::subscribe_typed`].
+///
+/// A message that isn't a `T` yields its decode error and the stream goes on.
+pub struct TypedSubscription<T> {
+    subscription: Subscription,
+    _payload: PhantomData<fn() -> T>,
+}
+
+impl<T> TypedSubscription<T> {
+    pub(crate) fn new(subscription: Subscription) -> Self {
+        TypedSubscription {
+            subscription,
+            _payload: PhantomData,
+        }
+    }
+
+    pub fn channel(&self) -> ChannelID {
+        self.subscription.channel()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.subscription.dropped()
+    }
+}
+
+impl<T: DeserializeOwned> TypedSubscription<T> {
+    pub async fn recv(&mut self) -> Option<Result<T, serde_json::Error>> {
+        let message = self.subscription.recv().await?;
+        Some(message.decode())
+    }
+}
+
+impl<T: DeserializeOwned> Stream for TypedSubscription<T> {
+    type Item = Result<T, serde_json::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let message = ready!(Pin::new(&mut self.subscription).poll_next(cx));
+        Poll::Ready(message.map(|message| message.decode()))
+    }
+}
+
+// Writes of a `ChannelStream` that may wait for the connection at once; further
+// writes return `Pending` until the oldest one is done.
+const STREAM_WRITE_WINDOW: usize = 16;
+
+/// Byte stream over the binary data of a channel, returned by [`DCClient::channel_stream`].
+///
+/// Reads yield the payloads of received `DataBin`s in order (text data is skipped),
+/// and each write is sent as one `DataBin`. Reads return EOF when the connection closes,
+/// and writes fail with `BrokenPipe`.
+pub struct ChannelStream {
+    client: DCClient,
+    subscription: Subscription,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    /// Writes not known to be done yet, oldest first.
+    pending_writes: VecDeque<oneshot::Receiver<WriteResult>>,
+}
+
+impl ChannelStream {
+    pub(crate) fn new(client: DCClient, subscription: Subscription) -> Self {
+        ChannelStream {
+            client,
+            subscription,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            pending_writes: VecDeque::new(),
+        }
+    }
+
+    pub fn channel(&self) -> ChannelID {
+        self.subscription.channel()
+    }
+
+    /// Forgets the writes that are done, failing with the first error among them.
+    /// Stays `Pending` while `limit` or more writes are not done.
+    fn poll_written(&mut self, cx: &mut Context<'_>, limit: usize) -> Poll<io::Result<()>> {
+        while let Some(oldest) = self.pending_writes.front_mut() {
+            let result = match Pin::new(oldest).poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending if self.pending_writes.len() < limit => break,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.pending_writes.pop_front();
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => return Poll::Ready(Err(io::Error::other(e))),
+                Err(_) => return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for ChannelStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.read_pos >= self.read_buf.len() {
+            match ready!(Pin::new(&mut self.subscription).poll_next(cx)) {
+                Some(Message::Binary { data, .. }) => {
+                    self.read_buf = data;
+                    self.read_pos = 0;
+                }
+                Some(Message::Text { .. }) => {}
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+
+        let available = &self.read_buf[self.read_pos..];
+        let len = available.len().min(buf.remaining());
+        buf.put_slice(&available[..len]);
+        self.read_pos += len;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for ChannelStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if !self.client.is_connected() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        ready!(self.poll_written(cx, STREAM_WRITE_WINDOW))?;
+
+        let channel = self.channel();
+        let written = self.client.enqueue(Event::DataBin {
+            channel,
+            from: 0,
+            data: buf.to_vec(),
+        });
+        self.pending_writes.push_back(written);
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_written(cx, 1)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use devconsole::{ChannelID, DCClient, Delivery, Event, NodeMeta};
+use tokio::{io::AsyncWriteExt, sync::mpsc, time::timeout};
+
+/// Connects a client to a fake server that accepts every listen request. Returns
+/// the sender of events to the client and the other events the client sends.
+async fn connect() -> (
+    DCClient,
+    mpsc::UnboundedSender<Event>,
+    mpsc::UnboundedReceiver<Event>,
+) {
+    let (to_server, mut from_client) = mpsc::unbounded_channel();
+    let (to_client, from_server) = mpsc::unbounded_channel();
+
+    let responder = to_client.clone();
+    let (seen, seen_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(event) = from_client.recv().await {
+            if let Event::ChannelListenRequest { channel } = event {
+                let response = Event::ChannelListenResponse {
+                    channel,
+                    success: true,
+                };
+                let _ = responder.send(response);
+            } else {
+                let _ = seen.send(event);
+            }
+        }
+    });
+
+    let client =
+        DCClient::new_loopback(NodeMeta::new("test", "test"), to_server, from_server).await;
+    (client, to_client, seen_rx)
+}
+
+fn data(channel: ChannelID, data: &str) -> Event {
+    Event::Data {
+        channel,
+        from: 1,
+        data: data.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn stalled_block_subscriber_does_not_stall_other_channels() {
+    let (client, server, _) = connect().await;
+    let mut stalled = client.subscribe_with(1, Delivery::Block(1)).await.unwrap();
+    let mut other = client.subscribe(2).await.unwrap();
+
+    for i in 0..10 {
+        server.send(data(1, &i.to_string())).unwrap();
+    }
+    server.send(data(2, "hello")).unwrap();
+
+    let message = timeout(Duration::from_secs(5), other.recv())
+        .await
+        .expect("delivery to channel 2 waited for channel 1")
+        .unwrap();
+    assert_eq!(message.as_bytes(), b"hello");
+
+    // nothing held back is lost
+    for i in 0..10 {
+        let message = timeout(Duration::from_secs(5), stalled.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.as_bytes(), i.to_string().as_bytes());
+    }
+}
+
+#[tokio::test]
+async fn overflowing_block_subscriber_drops_instead_of_stalling_the_reader() {
+    let (client, server, _) = connect().await;
+    let mut stalled = client.subscribe_with(1, Delivery::Block(1)).await.unwrap();
+
+    for i in 0..2000 {
+        server.send(data(1, &i.to_string())).unwrap();
+    }
+    // a request answered while the backlog is full
+    let mut other = timeout(Duration::from_secs(5), client.subscribe(2))
+        .await
+        .expect("the reader waited for channel 1")
+        .unwrap();
+    server.send(data(2, "done")).unwrap();
+    timeout(Duration::from_secs(5), other.recv()).await.unwrap();
+
+    let mut received = 0;
+    while let Ok(Some(_)) = timeout(Duration::from_millis(100), stalled.recv()).await {
+        received += 1;
+    }
+    assert!(stalled.dropped() > 0);
+    assert_eq!(received + stalled.dropped(), 2000);
+}
+
+#[tokio::test]
+async fn drop_oldest_subscriber_counts_discarded_messages() {
+    let (client, server, _) = connect().await;
+    let mut subscription = client
+        .subscribe_with(1, Delivery::DropOldest(2))
+        .await
+        .unwrap();
+    let mut marker = client.subscribe(2).await.unwrap();
+
+    for i in 0..5 {
+        server.send(data(1, &i.to_string())).unwrap();
+    }
+    // messages are delivered in order, so channel 1 is done once this arrives
+    server.send(data(2, "done")).unwrap();
+    timeout(Duration::from_secs(5), marker.recv())
+        .await
+        .unwrap();
+
+    assert_eq!(subscription.dropped(), 3);
+    assert_eq!(subscription.recv().await.unwrap().as_bytes(), b"3");
+    assert_eq!(subscription.recv().await.unwrap().as_bytes(), b"4");
+}
+
+#[tokio::test]
+async fn dropping_block_subscription_unlistens() {
+    let (client, _server, mut seen) = connect().await;
+    let subscription = client.subscribe_with(1, Delivery::Block(1)).await.unwrap();
+    drop(subscription);
+
+    loop {
+        let event = timeout(Duration::from_secs(5), seen.recv())
+            .await
+            .expect("no unlisten request")
+            .unwrap();
+        if let Event::ChannelUnlistenRequest { channel } = event {
+            assert_eq!(channel, 1);
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn channel_stream_writes_fail_once_the_connection_is_closed() {
+    let (client, _server, mut seen) = connect().await;
+    let mut stream = client.channel_stream(3).await.unwrap();
+
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    // the client announces its metadata first
+    loop {
+        match timeout(Duration::from_secs(5), seen.recv()).await.unwrap() {
+            Some(Event::DataBin { channel, data, .. }) => {
+                assert_eq!(channel, 3);
+                assert_eq!(data, b"hello");
+                break;
+            }
+            Some(_) => {}
+            None => panic!("the client closed the connection"),
+        }
+    }
+
+    client.close_timeout(Duration::from_millis(100)).await;
+    let error = stream.write(b"lost").await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+}
@@ -0,0 +1,228 @@
+//! `attach`: interactive serial-terminal style session on a channel.
+
+use std::io::{self, IsTerminal, Read, Write};
+
+use clap::ArgMatches;
+use crossterm::terminal;
+use devconsole::DCClient;
+use tokio::{select, sync::mpsc};
+
+use crate::{CliError, resolve_channel_id};
+
+/// Line ending of a channel, used both for what Enter sends and for received text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LineEnding {
+    Cr,
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::Cr => b"\r",
+            LineEnding::Lf => b"\n",
+            LineEnding::Crlf => b"\r\n",
+        }
+    }
+}
+
+/// Keeps the terminal in raw mode while alive.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Terminal {
+    echo: bool,
+    send_eol: LineEnding,
+    recv_eol: LineEnding,
+    /// Control code that opens the command menu (Ctrl + the configured letter).
+    escape: u8,
+    escaped: bool,
+}
+
+enum Input {
+    Send(Vec<u8>),
+    Quit,
+}
+
+impl Terminal {
+    /// Translates keystrokes into the bytes to send, handling the command menu.
+    fn input(&mut self, bytes: &[u8], out: &mut impl Write) -> io::Result<Input> {
+        let mut send = Vec::new();
+        for &b in bytes {
+            if self.escaped {
+                self.escaped = false;
+                match b {
+                    b'q' | b'x' => return Ok(Input::Quit),
+                    b'e' => {
+                        self.echo = !self.echo;
+                        let state = if self.echo { "ON" } else { "OFF" };
+                        write!(out, "\r\n*** ローカルエコー: {state} ***\r\n")?;
+                    }
+                    b'h' | b'?' => self.help(out)?,
+                    b if b == self.escape => send.push(b),
+                    _ => {}
+                }
+                continue;
+            }
+
+            if b == self.escape {
+                self.escaped = true;
+                continue;
+            }
+
+            if b == b'\r' {
+                send.extend_from_slice(self.send_eol.as_bytes());
+                if self.echo {
+                    out.write_all(b"\r\n")?;
+                }
+            } else {
+                send.push(b);
+                if self.echo {
+                    out.write_all(&[b])?;
+                }
+            }
+        }
+        out.flush()?;
+
+        Ok(Input::Send(send))
+    }
+
+    /// Writes received data, turning the channel's line endings into CRLF for the raw terminal.
+    fn output(&self, data: &[u8], out: &mut impl Write) -> io::Result<()> {
+        let newline = match self.recv_eol {
+            LineEnding::Crlf => {
+                out.write_all(data)?;
+                return out.flush();
+            }
+            LineEnding::Lf => b'\n',
+            LineEnding::Cr => b'\r',
+        };
+
+        let mut translated = Vec::with_capacity(data.len());
+        for &b in data {
+            if b == newline {
+                translated.extend_from_slice(b"\r\n");
+            } else {
+                translated.push(b);
+            }
+        }
+        out.write_all(&translated)?;
+        out.flush()
+    }
+
+    fn help(&self, out: &mut impl Write) -> io::Result<()> {
+        let key = (self.escape + b'a' - 1) as char;
+        write!(
+            out,
+            "\r\n*** コマンド (Ctrl-{key} の後に入力) ***\r\n\
+             ***   q, x    : 終了\r\n\
+             ***   e       : ローカルエコーの切り替え\r\n\
+             ***   h, ?    : このヘルプを表示\r\n\
+             ***   Ctrl-{key}  : Ctrl-{key} を送信\r\n"
+        )
+    }
+}
+
+/// Reads stdin on a plain thread: unlike `tokio::io::stdin`, a pending read
+/// does not keep the runtime from shutting down once the session ends.
+pub(crate) fn spawn_stdin_reader() -> mpsc::Receiver<io::Result<Vec<u8>>> {
+    let (tx, rx) = mpsc::channel(16);
+    std::thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        let mut buf = [0u8; 1024];
+        loop {
+            let read = match stdin.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => Ok(buf[..n].to_vec()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            if tx.blocking_send(read).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+pub async fn handle_attach(client: &DCClient, matches: &ArgMatches) -> Result<(), CliError> {
+    let channel_input = matches.get_one::<String>("channel").unwrap();
+    let escape_key = *matches.get_one::<char>("escape").unwrap();
+    if !escape_key.is_ascii_lowercase() {
+        return Err(CliError::InvalidArgument(format!(
+            "エスケープキーには a-z を指定してください: {escape_key}"
+        )));
+    }
+
+    let channel_id = resolve_channel_id(client, channel_input).await?;
+    let mut subscription = client
+        .subscribe(channel_id)
+        .await
+        .map_err(CliError::client("チャンネルの監視"))?;
+
+    let mut terminal = Terminal {
+        echo: matches.get_flag("echo"),
+        send_eol: *matches.get_one::<LineEnding>("eol").unwrap(),
+        recv_eol: *matches.get_one::<LineEnding>("recv-eol").unwrap(),
+        escape: escape_key as u8 - b'a' + 1,
+        escaped: false,
+    };
+
+    println!(
+        "チャンネル {channel_id} に接続しました。Ctrl-{escape_key} q で終了、Ctrl-{escape_key} h でヘルプを表示します。"
+    );
+
+    // stdin may be a pipe, in which case it is forwarded as is
+    let _raw_mode = if io::stdin().is_terminal() {
+        Some(RawMode::enable().map_err(CliError::Io)?)
+    } else {
+        None
+    };
+
+    let mut stdin = spawn_stdin_reader();
+    let mut stdout = io::stdout();
+
+    loop {
+        select! {
+            message = subscription.recv() => {
+                let Some(message) = message else {
+                    write!(stdout, "\r\n*** 接続が切断されました ***\r\n").map_err(CliError::Io)?;
+                    break;
+                };
+                terminal.output(message.as_bytes(), &mut stdout).map_err(CliError::Io)?;
+            }
+            read = stdin.recv() => {
+                let Some(read) = read else {
+                    break;
+                };
+                let keys = read.map_err(CliError::Io)?;
+                match terminal.input(&keys, &mut stdout).map_err(CliError::Io)? {
+                    Input::Send(data) if !data.is_empty() => {
+                        client
+                            .send_bin(channel_id, data)
+                            .await
+                            .map_err(CliError::client("データの送信"))?;
+                    }
+                    Input::Send(_) => {}
+                    Input::Quit => break,
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,441 @@
+//! `listen`: watch one or more channels.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, IsTerminal, Write},
+    time::Duration,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chrono::{DateTime, Local, SecondsFormat};
+use clap::ArgMatches;
+use crossterm::style::{Color, Stylize};
+use devconsole::{ChannelID, DCClient, Message, NodeID};
+use serde_json::json;
+use tokio::{
+    select,
+    sync::mpsc,
+    time::{Instant, sleep_until},
+};
+
+use crate::{CliError, resolve_channel_id};
+
+// How long `--merge` holds a message back so that one stamped earlier on another
+// channel can still be printed before it.
+const REORDER_WINDOW: Duration = Duration::from_millis(50);
+// Stamped messages queued for printing before the channel tasks wait.
+const RECEIVED_QUEUE: usize = 256;
+
+const PALETTE: [Color; 6] = [
+    Color::Cyan,
+    Color::Yellow,
+    Color::Green,
+    Color::Magenta,
+    Color::Blue,
+    Color::Red,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Exact received bytes, for piping into other tools.
+    Raw,
+    /// Text with control and non-ASCII bytes of binary data escaped.
+    Escaped,
+    Hexdump,
+    /// One JSON object per message.
+    Jsonl,
+    /// One base64 line per message.
+    Base64,
+}
+
+impl OutputFormat {
+    /// Whether stdout carries nothing but the data, so notices go to stderr.
+    fn is_machine_readable(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Raw | OutputFormat::Jsonl | OutputFormat::Base64
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+struct Channel {
+    id: ChannelID,
+    name: String,
+    color: Color,
+    /// Received data not terminated by a newline yet.
+    partial: String,
+    /// Bytes received so far, the offset shown by the hex dump.
+    offset: usize,
+}
+
+/// Matches `name` against a pattern where `*` is any run of characters and `?` any one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and the name position it is matched up to
+    let mut star = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Resolves channel IDs, names and name patterns to the channels to listen to.
+async fn resolve_channels(
+    client: &DCClient,
+    inputs: &[String],
+) -> Result<Vec<(ChannelID, String)>, CliError> {
+    let mut channels: Vec<(ChannelID, String)> = Vec::new();
+    let mut push = |id, name| {
+        if !channels.iter().any(|&(c, _)| c == id) {
+            channels.push((id, name));
+        }
+    };
+
+    for input in inputs {
+        if !input.contains(['*', '?']) {
+            let id = resolve_channel_id(client, input).await?;
+            let name = match client.channel_info(id).await {
+                Ok(info) => info.name,
+                Err(_) => id.to_string(),
+            };
+            push(id, name);
+            continue;
+        }
+
+        let ids = client
+            .channel_list()
+            .await
+            .map_err(CliError::client("チャンネル一覧の取得"))?;
+        let mut matched = false;
+        for id in ids {
+            let Ok(info) = client.channel_info(id).await else {
+                continue;
+            };
+            if glob_match(input, &info.name) {
+                push(id, info.name);
+                matched = true;
+            }
+        }
+        if !matched {
+            return Err(CliError::ChannelNotFound(input.clone()));
+        }
+    }
+
+    Ok(channels)
+}
+
+/// Escapes control and non-ASCII bytes of binary data for display.
+pub(crate) fn escape_binary(data: &[u8]) -> String {
+    let mut s = String::new();
+    for &b in data {
+        match b {
+            b'\x1b' => s.push_str(r"\e"),
+            b'\n' => s.push('\n'),
+            b'\r' => s.push_str(r"\r"),
+            b'\t' => s.push_str(r"\t"),
+            b'\0' => s.push_str(r"\0"),
+            0x20..=0x7e => s.push(b as char),
+            _ => s.push_str(&format!(r"\x{b:02X}")),
+        }
+    }
+    s
+}
+
+fn display_text(message: &Message) -> String {
+    match message {
+        Message::Text { data, .. } => data.clone(),
+        Message::Binary { data, .. } => escape_binary(data),
+    }
+}
+
+/// `xxd`-style dump of `data`, numbering bytes from `offset`.
+pub(crate) fn hexdump(data: &[u8], offset: usize) -> String {
+    let mut dump = String::new();
+    for (i, row) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = row.iter().map(|b| format!("{b:02x}")).collect();
+        let ascii: String = row
+            .iter()
+            .map(|&b| match b {
+                0x20..=0x7e => b as char,
+                _ => '.',
+            })
+            .collect();
+        dump.push_str(&format!(
+            "{:08x}: {:<47}  {ascii}\n",
+            offset + i * 16,
+            hex.join(" ")
+        ));
+    }
+    dump
+}
+
+struct Printer<'a> {
+    client: &'a DCClient,
+    format: OutputFormat,
+    colored: bool,
+    /// Prefix lines with time, channel and sender.
+    prefixed: bool,
+    /// Print each message as it arrives instead of assembling lines per channel.
+    merge: bool,
+    newline: bool,
+    /// Declared names of the senders, `None` if unknown.
+    senders: HashMap<NodeID, Option<String>>,
+}
+
+impl Printer<'_> {
+    async fn sender_meta_name(&mut self, node_id: NodeID) -> Option<String> {
+        if let Some(name) = self.senders.get(&node_id) {
+            return name.clone();
+        }
+
+        let name = match self.client.node_info(node_id).await {
+            Ok(info) => info.meta.map(|meta| meta.name),
+            Err(_) => None,
+        };
+        self.senders.insert(node_id, name.clone());
+        name
+    }
+
+    async fn sender_name(&mut self, node_id: NodeID) -> String {
+        match self.sender_meta_name(node_id).await {
+            Some(name) => format!("{name}({node_id})"),
+            None => node_id.to_string(),
+        }
+    }
+
+    fn prefix(&self, time: DateTime<Local>, channel: &Channel, sender: &str) -> String {
+        let time = time.format("%H:%M:%S%.3f");
+        let name = format!("[{}]", channel.name);
+        if self.colored {
+            format!(
+                "{} {} {}> ",
+                time.to_string().dark_grey(),
+                name.with(channel.color),
+                sender
+            )
+        } else {
+            format!("{time} {name} {sender}> ")
+        }
+    }
+
+    async fn print(
+        &mut self,
+        time: DateTime<Local>,
+        channel: &mut Channel,
+        message: &Message,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Raw => out.write_all(message.as_bytes())?,
+            OutputFormat::Escaped => return self.print_escaped(time, channel, message, out).await,
+            OutputFormat::Hexdump => {
+                if self.prefixed {
+                    let sender = self.sender_name(message.from()).await;
+                    writeln!(out, "{}", self.prefix(time, channel, &sender).trim_end())?;
+                }
+                let data = message.as_bytes();
+                out.write_all(hexdump(data, channel.offset).as_bytes())?;
+                channel.offset += data.len();
+            }
+            OutputFormat::Jsonl => {
+                let (kind, data) = match message {
+                    Message::Text { data, .. } => ("text", data.clone()),
+                    Message::Binary { data, .. } => ("binary", BASE64.encode(data)),
+                };
+                let record = json!({
+                    "time": time.to_rfc3339_opts(SecondsFormat::Millis, false),
+                    "channel": channel.id,
+                    "channel_name": channel.name,
+                    "from": message.from(),
+                    "sender": self.sender_meta_name(message.from()).await,
+                    "direct": message.is_direct(),
+                    "kind": kind,
+                    "data": data,
+                });
+                writeln!(out, "{record}")?;
+            }
+            OutputFormat::Base64 => {
+                if self.prefixed {
+                    let sender = self.sender_name(message.from()).await;
+                    write!(out, "{}", self.prefix(time, channel, &sender))?;
+                }
+                writeln!(out, "{}", BASE64.encode(message.as_bytes()))?;
+            }
+        }
+        out.flush()
+    }
+
+    async fn print_escaped(
+        &mut self,
+        time: DateTime<Local>,
+        channel: &mut Channel,
+        message: &Message,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut text = display_text(message);
+        if self.newline {
+            text.push('\n');
+        }
+
+        if !self.prefixed {
+            out.write_all(text.as_bytes())?;
+            return out.flush();
+        }
+
+        let sender = self.sender_name(message.from()).await;
+        let prefix = self.prefix(time, channel, &sender);
+
+        if self.merge {
+            let text = text.strip_suffix('\n').unwrap_or(&text);
+            writeln!(out, "{prefix}{text}")?;
+            return out.flush();
+        }
+
+        channel.partial.push_str(&text);
+        while let Some(end) = channel.partial.find('\n') {
+            let line: String = channel.partial.drain(..=end).collect();
+            write!(out, "{prefix}{line}")?;
+        }
+        out.flush()
+    }
+}
+
+pub async fn handle_listen(client: &DCClient, matches: &ArgMatches) -> Result<(), CliError> {
+    let inputs: Vec<String> = matches
+        .get_many::<String>("channel")
+        .unwrap()
+        .cloned()
+        .collect();
+    let resolved = resolve_channels(client, &inputs).await?;
+
+    let single = inputs.len() == 1 && resolved.len() == 1 && !inputs[0].contains(['*', '?']);
+    let colored = match *matches.get_one::<ColorMode>("color").unwrap() {
+        ColorMode::Auto => io::stdout().is_terminal(),
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+    };
+    let format = *matches.get_one::<OutputFormat>("format").unwrap();
+    let mut printer = Printer {
+        client,
+        format,
+        colored,
+        prefixed: !single || matches.get_flag("prefix") || matches.get_flag("merge"),
+        merge: matches.get_flag("merge"),
+        newline: matches.get_flag("newline"),
+        senders: HashMap::new(),
+    };
+
+    // every channel stamps its messages on receipt and feeds them to one queue
+    let (queue, mut received) = mpsc::channel(RECEIVED_QUEUE);
+    let mut channels = Vec::new();
+    for (index, (id, name)) in resolved.into_iter().enumerate() {
+        let mut subscription = client
+            .subscribe(id)
+            .await
+            .map_err(CliError::client("チャンネルの監視"))?;
+        let queue = queue.clone();
+        tokio::spawn(async move {
+            while let Some(message) = subscription.recv().await {
+                let stamped = (Local::now(), Instant::now(), index, message);
+                if queue.send(stamped).await.is_err() {
+                    break;
+                }
+            }
+        });
+        channels.push(Channel {
+            id,
+            name,
+            color: PALETTE[index % PALETTE.len()],
+            partial: String::new(),
+            offset: 0,
+        });
+    }
+
+    let names: Vec<String> = channels
+        .iter()
+        .map(|c| format!("{} ({})", c.name, c.id))
+        .collect();
+    let notice = format!(
+        "チャンネル {} を監視しています。Ctrl+C で終了します。",
+        names.join(", ")
+    );
+    if format.is_machine_readable() {
+        eprintln!("{notice}");
+    } else {
+        println!("{notice}");
+    }
+
+    drop(queue);
+
+    let window = if printer.merge {
+        REORDER_WINDOW
+    } else {
+        Duration::ZERO
+    };
+    // messages waiting out the window, ordered by receipt time; the
+    // sequence number keeps messages stamped at the same time apart
+    let mut pending: BTreeMap<(DateTime<Local>, u64), (Instant, usize, Message)> = BTreeMap::new();
+    let mut seq = 0u64;
+    let mut open = true;
+    let mut stdout = io::stdout();
+    while open || !pending.is_empty() {
+        let due = pending.first_key_value().map(|(_, &(at, ..))| at + window);
+        select! {
+            stamped = received.recv(), if open => {
+                match stamped {
+                    Some((time, at, index, message)) => {
+                        pending.insert((time, seq), (at, index, message));
+                        seq += 1;
+                    }
+                    None => open = false,
+                }
+            }
+            () = sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() && open => {}
+            // the connection is gone; print what is still held back
+            else => {}
+        }
+
+        while let Some(entry) = pending.first_entry() {
+            if open && entry.get().0 + window > Instant::now() {
+                break;
+            }
+            let ((time, _), (_, index, message)) = entry.remove_entry();
+            printer
+                .print(time, &mut channels[index], &message, &mut stdout)
+                .await
+                .map_err(CliError::Io)?;
+        }
+    }
+
+    // the connection is gone; show what is left of unterminated lines
+    for channel in &channels {
+        if !channel.partial.is_empty() {
+            println!("{}", channel.partial);
+        }
+    }
+
+    Ok(())
+}
@@ -1,8 +1,74 @@
+mod attach;
+mod listen;
+mod pipe;
+mod pktuart;
+mod script;
+mod send;
+mod serial;
+mod tui;
+mod watch;
+
+use attach::{LineEnding, handle_attach};
 use clap::{Arg, ArgMatches, Command};
-use devconsole::{ChannelID, DCClient};
+use devconsole::{ChannelID, DCClient, DCClientError, NodeID, NodeMeta};
+use listen::{ColorMode, OutputFormat, handle_listen};
 use log::error;
-use std::io::{self, Write};
-use tokio::{select, sync::mpsc};
+use pipe::{handle_exec, handle_pipe};
+use pktuart::{handle_pktuart, parse_address};
+use script::handle_script;
+use send::handle_send;
+use serde::Serialize;
+use serial::handle_serial;
+use std::io;
+use tui::handle_tui;
+use watch::handle_watch;
+
+#[derive(Debug)]
+enum CliError {
+    /// A request failed; `action` describes what was being done.
+    Client {
+        action: &'static str,
+        source: DCClientError,
+    },
+    ChannelNotFound(String),
+    InvalidArgument(String),
+    Io(io::Error),
+    /// A process started by `exec` exited unsuccessfully.
+    ProcessFailed(std::process::ExitStatus),
+    /// The node serving the named channel did not answer in time.
+    NoResponse(String),
+    /// A test script could not be loaded.
+    Script(String),
+    ScriptFailed {
+        failed: usize,
+        total: usize,
+    },
+}
+
+impl CliError {
+    fn client(action: &'static str) -> impl FnOnce(DCClientError) -> CliError {
+        move |source| CliError::Client { action, source }
+    }
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::Client { action, source } => write!(f, "{action}に失敗しました: {source}"),
+            CliError::ChannelNotFound(name) => write!(f, "チャンネル '{name}' が見つかりません"),
+            CliError::InvalidArgument(e) => write!(f, "引数が不正です: {e}"),
+            CliError::Io(e) => write!(f, "入出力エラー: {e}"),
+            CliError::ProcessFailed(status) => write!(f, "プロセスが異常終了しました: {status}"),
+            CliError::NoResponse(channel) => {
+                write!(f, "チャンネル '{channel}' の提供者から応答がありません")
+            }
+            CliError::Script(e) => write!(f, "スクリプトが不正です: {e}"),
+            CliError::ScriptFailed { failed, total } => {
+                write!(f, "{total} 件中 {failed} 件のテストが失敗しました")
+            }
+        }
+    }
+}
 
 #[tokio::main]
 pub async fn main() {
@@ -28,7 +94,14 @@ pub async fn main() {
             Arg::new("verbose")
                 .short('v')
                 .long("verbose")
-                .help("Node ID を表示")
+                .help("Node ID を表示（--json 指定時は表示しない）")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("list・info・node・watch・serial・pktuart・script の結果を JSON で出力")
+                .global(true)
                 .action(clap::ArgAction::SetTrue),
         )
         .subcommand(
@@ -36,16 +109,46 @@ pub async fn main() {
                 .about("指定したチャンネルを監視し、受信したメッセージを表示")
                 .arg(
                     Arg::new("channel")
-                        .help("チャンネル名またはID")
+                        .help("チャンネル名・ID・名前のパターン（* と ? が使用可能）。複数指定可")
                         .required(true)
+                        .num_args(1..)
                         .index(1),
                 )
                 .arg(
                     Arg::new("newline")
                         .short('n')
-                        .help("メッセージの後に改行を追加")
+                        .help("メッセージの後に改行を追加（escaped 形式のみ）")
                         .required(false)
                         .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("format")
+                        .short('f')
+                        .long("format")
+                        .help("出力形式")
+                        .value_parser(clap::value_parser!(OutputFormat))
+                        .default_value("escaped"),
+                )
+                .arg(
+                    Arg::new("prefix")
+                        .short('p')
+                        .long("prefix")
+                        .help("各行の先頭に時刻・チャンネル名・送信元を表示（複数チャンネルでは常に有効）")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("merge")
+                        .short('m')
+                        .long("merge")
+                        .help("行単位にまとめず、受信したメッセージを受信順にそのまま表示")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("color")
+                        .long("color")
+                        .help("チャンネルごとの色分け")
+                        .value_parser(clap::value_parser!(ColorMode))
+                        .default_value("auto"),
                 ),
         )
         .subcommand(
@@ -67,14 +170,132 @@ pub async fn main() {
                 .arg(
                     Arg::new("message")
                         .help("送信するメッセージ")
-                        .required(true)
+                        .required_unless_present_any(["stdin", "file"])
                         .index(2),
+                )
+                .arg(
+                    Arg::new("stdin")
+                        .long("stdin")
+                        .help("標準入力の内容を送信（テキストは1行ずつ、-b ではブロックごと）")
+                        .conflicts_with_all(["message", "file"])
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("file")
+                        .long("file")
+                        .value_name("PATH")
+                        .help("ファイルの内容を送信（テキストは1行ずつ、-b ではブロックごと）")
+                        .conflicts_with("message"),
+                )
+                .arg(
+                    Arg::new("chunk-size")
+                        .long("chunk-size")
+                        .value_name("BYTES")
+                        .help("バイナリモードで1回に送信するバイト数")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("256"),
+                )
+                .arg(
+                    Arg::new("delay")
+                        .long("delay")
+                        .value_name("MS")
+                        .help("行・ブロックの送信間隔（ミリ秒）")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("rate")
+                        .long("rate")
+                        .value_name("BYTES_PER_SEC")
+                        .help("送信レートの上限（バイト/秒）")
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                ),
+        )
+        .subcommand(
+            Command::new("attach")
+                .about("シリアルターミナルのようにチャンネルと対話（キー入力をバイナリで送信）")
+                .arg(
+                    Arg::new("channel")
+                        .help("チャンネル名またはID")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("echo")
+                        .short('e')
+                        .long("echo")
+                        .help("入力した文字をローカルエコー")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("eol")
+                        .long("eol")
+                        .help("Enter キーで送信する改行コード")
+                        .value_parser(clap::value_parser!(LineEnding))
+                        .default_value("cr"),
+                )
+                .arg(
+                    Arg::new("recv-eol")
+                        .long("recv-eol")
+                        .help("受信データの改行コード（表示時に CRLF に変換）")
+                        .value_parser(clap::value_parser!(LineEnding))
+                        .default_value("lf"),
+                )
+                .arg(
+                    Arg::new("escape")
+                        .long("escape")
+                        .value_name("KEY")
+                        .help("コマンドメニューを開くキー（Ctrl + KEY）")
+                        .value_parser(clap::value_parser!(char))
+                        .default_value("a"),
+                ),
+        )
+        .subcommand(
+            Command::new("exec")
+                .about("コマンドを起動し、その標準入出力をチャンネルに接続（バイナリ）")
+                .arg(
+                    Arg::new("channel")
+                        .help("チャンネル名またはID")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("command")
+                        .help("起動するコマンドと引数（-- の後に指定）")
+                        .required(true)
+                        .num_args(1..)
+                        .last(true),
+                ),
+        )
+        .subcommand(
+            Command::new("pipe")
+                .about("CLI の標準入出力をチャンネルに接続（バイナリ）")
+                .arg(
+                    Arg::new("channel")
+                        .help("チャンネル名またはID")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("linger")
+                        .long("linger")
+                        .value_name("MS")
+                        .help("標準入力の終了後も受信を続ける時間（ミリ秒）")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("0"),
                 ),
         )
         .subcommand(Command::new("list").about("利用可能なチャンネル一覧を表示"))
+        .subcommand(Command::new("watch").about("チャンネルの作成・削除を監視して表示"))
         .subcommand(
             Command::new("open")
                 .about("指定した名前でチャンネルを開く")
+                .arg(
+                    Arg::new("persistent")
+                        .short('p')
+                        .long("persistent")
+                        .help("切断後も一定時間チャンネルを保持")
+                        .required(false)
+                        .action(clap::ArgAction::SetTrue),
+                )
                 .arg(
                     Arg::new("name")
                         .help("チャンネル名")
@@ -82,6 +303,180 @@ pub async fn main() {
                         .index(1),
                 ),
         )
+        .subcommand(
+            Command::new("serial")
+                .about("シリアルモニターの操作")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("list").about("監視中のシリアルポートを表示").arg(
+                        Arg::new("timeout")
+                            .long("timeout")
+                            .value_name("MS")
+                            .help("応答を待つ時間（ミリ秒）")
+                            .value_parser(clap::value_parser!(u64))
+                            .default_value("2000"),
+                    ),
+                )
+                .subcommand(
+                    Command::new("open-vport")
+                        .about("シリアルポートの入出力をチャンネルに転送する仮想ポートを開く")
+                        .arg(
+                            Arg::new("path")
+                                .help("シリアルポートのパス（例: /dev/ttyACM0）")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("name")
+                                .help("転送先のチャンネル名")
+                                .required(true)
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::new("timeout")
+                                .long("timeout")
+                                .value_name("MS")
+                                .help("応答を待つ時間（ミリ秒）")
+                                .value_parser(clap::value_parser!(u64))
+                                .default_value("2000"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("write")
+                        .about("シリアルポートにデータを書き込む")
+                        .arg(
+                            Arg::new("path")
+                                .help("シリアルポートのパス")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("data")
+                                .help("送信するデータ（\\n, \\r, \\t, \\e, \\0, \\xHH のエスケープが使用可能）")
+                                .required(true)
+                                .index(2),
+                        ),
+                )
+                .subcommand(
+                    Command::new("watch")
+                        .about("シリアルポートの接続・切断と受信した行を表示")
+                        .arg(
+                            Arg::new("path")
+                                .help("表示するシリアルポートのパス（省略時はすべて）")
+                                .num_args(0..)
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("pktuart")
+                .about("dc_pktuart のブリッジの作成とパケットの送受信")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("bridge")
+                        .about("チャンネルの PktUART フレームを別のチャンネルに中継するブリッジを作成")
+                        .arg(
+                            Arg::new("src")
+                                .help("PktUART フレームが流れるチャンネル名またはID")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("dst")
+                                .help("デコードしたパケットを送受信するチャンネル名")
+                                .required(true)
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::new("timeout")
+                                .long("timeout")
+                                .value_name("MS")
+                                .help("ブリッジの作成を待つ時間（ミリ秒）")
+                                .value_parser(clap::value_parser!(u64))
+                                .default_value("2000"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("send")
+                        .about("ブリッジのチャンネルにパケットを送信")
+                        .arg(
+                            Arg::new("hex")
+                                .short('x')
+                                .long("hex")
+                                .help("ペイロードを16進数のバイト列として解釈（例: 55aa5a）")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("channel")
+                                .help("チャンネル名またはID")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("addr")
+                                .help("宛先アドレス（10進数または 0x で始まる16進数）")
+                                .required(true)
+                                .value_parser(parse_address)
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::new("payload")
+                                .help("ペイロード（send -b と同じエスケープが使用可能）")
+                                .required(true)
+                                .index(3),
+                        ),
+                )
+                .subcommand(
+                    Command::new("listen")
+                        .about("ブリッジのチャンネルのパケットをアドレスごとに表示")
+                        .arg(
+                            Arg::new("channel")
+                                .help("チャンネル名またはID")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("addr")
+                                .short('a')
+                                .long("addr")
+                                .help("表示するアドレス")
+                                .value_parser(parse_address),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("script")
+                .about("TOML または YAML で記述したテストスクリプトの実行")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("run")
+                        .about("スクリプトを実行し、失敗したテストがあれば終了コード 1 で終了")
+                        .arg(
+                            Arg::new("file")
+                                .help("スクリプトファイル（拡張子が .yaml・.yml なら YAML、それ以外は TOML）")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("var")
+                                .short('D')
+                                .long("var")
+                                .value_name("KEY=VALUE")
+                                .help("スクリプトで ${KEY} として参照する変数（複数指定可）")
+                                .action(clap::ArgAction::Append),
+                        )
+                        .arg(
+                            Arg::new("junit")
+                                .long("junit")
+                                .value_name("PATH")
+                                .help("JUnit 形式のレポートの出力先"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("tui")
+                .about("チャンネル・ノードの一覧と受信データを表示するフルスクリーンのダッシュボード"),
+        )
         .subcommand(
             Command::new("info")
                 .about("指定したチャンネルの情報を表示")
@@ -92,11 +487,21 @@ pub async fn main() {
                         .index(1),
                 ),
         )
+        .subcommand(
+            Command::new("node")
+                .about("指定したノードの情報を表示")
+                .arg(
+                    Arg::new("node")
+                        .help("ノードID（省略時は CLI 自身）")
+                        .value_parser(clap::value_parser!(NodeID))
+                        .index(1),
+                ),
+        )
         .get_matches();
 
     let server_addr = matches.get_one::<String>("server").unwrap();
 
-    let mut client = match DCClient::new(server_addr).await {
+    let client = match DCClient::new_with_meta(server_addr, NodeMeta::new("cli", "cli")).await {
         Ok(client) => client,
         Err(e) => {
             error!("サーバーへの接続に失敗しました: {e}");
@@ -107,7 +512,10 @@ pub async fn main() {
     // Wait a bit to receive NodeID notification
     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
 
-    if matches.get_flag("verbose") {
+    let json = matches.get_flag("json");
+
+    // stdout carries nothing but the JSON output in JSON mode
+    if matches.get_flag("verbose") && !json {
         if let Some(node_id) = client.get_node_id().await {
             println!("Node ID: {node_id}");
         } else {
@@ -117,55 +525,106 @@ pub async fn main() {
 
     match matches.subcommand() {
         Some(("listen", sub_matches)) => {
-            if let Err(e) = handle_listen(&mut client, sub_matches).await {
+            if let Err(e) = handle_listen(&client, sub_matches).await {
                 error!("Listen コマンドでエラーが発生しました: {e}");
                 std::process::exit(1);
             }
         }
         Some(("send", sub_matches)) => {
-            if let Err(e) = handle_send(&mut client, sub_matches).await {
+            if let Err(e) = handle_send(&client, sub_matches).await {
                 error!("Send コマンドでエラーが発生しました: {e}");
                 std::process::exit(1);
             }
         }
+        Some(("attach", sub_matches)) => {
+            if let Err(e) = handle_attach(&client, sub_matches).await {
+                error!("Attach コマンドでエラーが発生しました: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some(("exec", sub_matches)) => {
+            if let Err(e) = handle_exec(&client, sub_matches).await {
+                error!("Exec コマンドでエラーが発生しました: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some(("pipe", sub_matches)) => {
+            if let Err(e) = handle_pipe(&client, sub_matches).await {
+                error!("Pipe コマンドでエラーが発生しました: {e}");
+                std::process::exit(1);
+            }
+        }
         Some(("list", _)) => {
-            if let Err(e) = handle_list(&mut client).await {
+            if let Err(e) = handle_list(&client, json).await {
                 error!("List コマンドでエラーが発生しました: {e}");
                 std::process::exit(1);
             }
         }
+        Some(("watch", _)) => {
+            if let Err(e) = handle_watch(&client, json).await {
+                error!("Watch コマンドでエラーが発生しました: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some(("serial", sub_matches)) => {
+            if let Err(e) = handle_serial(&client, sub_matches, json).await {
+                error!("Serial コマンドでエラーが発生しました: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some(("pktuart", sub_matches)) => {
+            if let Err(e) = handle_pktuart(&client, sub_matches, json).await {
+                error!("PktUART コマンドでエラーが発生しました: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some(("script", sub_matches)) => {
+            if let Err(e) = handle_script(&client, sub_matches, json).await {
+                error!("Script コマンドでエラーが発生しました: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some(("tui", _)) => {
+            if let Err(e) = handle_tui(&client).await {
+                error!("Tui コマンドでエラーが発生しました: {e}");
+                std::process::exit(1);
+            }
+        }
         Some(("open", sub_matches)) => {
-            if let Err(e) = handle_open(&mut client, sub_matches).await {
+            if let Err(e) = handle_open(&client, sub_matches).await {
                 error!("Open コマンドでエラーが発生しました: {e}");
                 std::process::exit(1);
             }
         }
         Some(("info", sub_matches)) => {
-            if let Err(e) = handle_info(&mut client, sub_matches).await {
+            if let Err(e) = handle_info(&client, sub_matches, json).await {
                 error!("Info コマンドでエラーが発生しました: {e}");
                 std::process::exit(1);
             }
         }
+        Some(("node", sub_matches)) => {
+            if let Err(e) = handle_node(&client, sub_matches, json).await {
+                error!("Node コマンドでエラーが発生しました: {e}");
+                std::process::exit(1);
+            }
+        }
         _ => {
             println!("コマンドを指定してください。--help でヘルプを表示します。");
         }
     }
 }
 
-async fn resolve_channel_id(
-    client: &mut DCClient,
-    channel_input: &str,
-) -> Result<ChannelID, String> {
+async fn resolve_channel_id(client: &DCClient, channel_input: &str) -> Result<ChannelID, CliError> {
     // Try to parse as numeric ID first
     if let Ok(channel_id) = channel_input.parse::<ChannelID>() {
         return Ok(channel_id);
     }
 
     // If not numeric, search by name
-    let channels = match client.channel_list().await {
-        Ok(channels) => channels,
-        Err(e) => return Err(format!("チャンネル一覧の取得に失敗しました: {e}")),
-    };
+    let channels = client
+        .channel_list()
+        .await
+        .map_err(CliError::client("チャンネル一覧の取得"))?;
 
     for &channel_id in &channels {
         match client.channel_info(channel_id).await {
@@ -178,137 +637,55 @@ async fn resolve_channel_id(
         }
     }
 
-    Err(format!("チャンネル '{channel_input}' が見つかりません"))
+    Err(CliError::ChannelNotFound(channel_input.to_string()))
 }
 
-async fn handle_listen(client: &mut DCClient, matches: &ArgMatches) -> Result<(), String> {
-    let channel_input = matches.get_one::<String>("channel").unwrap();
-    let newline = matches.get_one::<bool>("newline").unwrap();
-    let channel_id = resolve_channel_id(client, channel_input).await?;
-
-    let (tx, mut rx) = mpsc::channel::<(ChannelID, String)>(64);
-    let (tx_bin, mut rx_bin) = mpsc::channel::<(ChannelID, Vec<u8>)>(64);
-    client
-        .listen(channel_id, Some(tx), Some(tx_bin))
+/// Finds the channel named `name` supplied by the node `supplier`.
+async fn find_supplied_by(
+    client: &DCClient,
+    name: &str,
+    supplier: NodeID,
+) -> Result<Option<ChannelID>, CliError> {
+    let channels = client
+        .channel_list()
         .await
-        .map_err(|e| format!("チャンネルの監視に失敗しました: {e}"))?;
-
-    println!("チャンネル {channel_id} を監視しています。Ctrl+C で終了します。");
-    loop {
-        select! {
-            Some((_, message)) = rx.recv() => {
-                if *newline {
-                    println!("{message}");
-                } else {
-                    print!("{message}");
-                }
-                io::stdout().flush().ok();
-            }
-            Some((_, data)) = rx_bin.recv() => {
-                let mut s = String::new();
-                for &b in &data {
-                    match b {
-                        b'\x1b' => s.push_str(r"\e"),
-                        b'\n' => s.push_str("\n"),
-                        b'\r' => s.push_str(r"\r"),
-                        b'\t' => s.push_str(r"\t"),
-                        b'\0' => s.push_str(r"\0"),
-                        0x20..=0x7e => s.push(b as char),
-                        _ => s.push_str(&format!(r"\x{b:02X}")),
-                    }
-                }
-                if *newline {
-                    s.push('\n');
-                }
-
-                print!("{s}");
-                io::stdout().flush().ok();
-            }
-            else => {
-                // Both channels closed
-                break;
-            }
-        }
-    }
-
-    Ok(())
-}
-
-fn intercept_escape_sequences(input: &str) -> Vec<u8> {
-    let mut output = Vec::new();
-    let mut chars = input.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        if c != '\\' {
-            output.push(c as u8);
-            continue;
-        }
-        let next_char = match chars.next() {
-            Some(nc) => nc,
-            None => {
-                output.push(b'\\');
-                break;
-            }
-        };
-        match next_char {
-            'n' => output.push(b'\n'),
-            'r' => output.push(b'\r'),
-            't' => output.push(b'\t'),
-            'e' => output.push(b'\x1b'),
-            '0' => output.push(b'\0'),
-            'x' => {
-                let hex1 = chars.next();
-                let hex2 = chars.next();
-                if let (Some(h1), Some(h2)) = (hex1, hex2) {
-                    if let (Some(d1), Some(d2)) = (h1.to_digit(16), h2.to_digit(16)) {
-                        output.push(((d1 << 4) | d2) as u8);
-                    } else {
-                        output.push(b'\\');
-                        output.push(b'x');
-                        output.push(h1 as u8);
-                        output.push(h2 as u8);
-                    }
-                }
-            }
-            _ => {
-                output.push(b'\\');
-                output.push(next_char as u8);
-            }
+        .map_err(CliError::client("チャンネル一覧の取得"))?;
+
+    for channel in channels {
+        // a channel closed since the list was taken is skipped
+        if let Ok(info) = client.channel_info(channel).await
+            && info.name == name
+            && info.supplied_by == supplier
+        {
+            return Ok(Some(channel));
         }
     }
 
-    output
+    Ok(None)
 }
 
-async fn handle_send(client: &mut DCClient, matches: &ArgMatches) -> Result<(), String> {
-    let channel_input = matches.get_one::<String>("channel").unwrap();
-    let message = matches.get_one::<String>("message").unwrap();
-    let is_binary = matches.get_flag("binary");
-
-    let channel_id = resolve_channel_id(client, channel_input).await?;
-
-    if is_binary {
-        client
-            .send_bin(channel_id, intercept_escape_sequences(message))
-            .await
-            .map_err(|e| format!("メッセージの送信に失敗しました: {e}"))?;
-    } else {
-        client
-            .send(channel_id, message.clone())
-            .await
-            .map_err(|e| format!("メッセージの送信に失敗しました: {e}"))?;
-    }
-
-    println!("チャンネル {channel_id} にメッセージを送信しました: {message}");
-
-    Ok(())
+/// Prints `value` as pretty-printed JSON on stdout.
+fn print_json(value: &impl Serialize) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
 }
 
-async fn handle_list(client: &mut DCClient) -> Result<(), String> {
+async fn handle_list(client: &DCClient, json: bool) -> Result<(), CliError> {
     let channels = client
         .channel_list()
         .await
-        .map_err(|e| format!("チャンネル一覧の取得に失敗しました: {e}"))?;
+        .map_err(CliError::client("チャンネル一覧の取得"))?;
+
+    if json {
+        let mut infos = Vec::new();
+        for &channel_id in &channels {
+            // a channel closed since the list was taken is left out
+            if let Ok(info) = client.channel_info(channel_id).await {
+                infos.push(info);
+            }
+        }
+        print_json(&infos);
+        return Ok(());
+    }
 
     if channels.is_empty() {
         println!("利用可能なチャンネルはありません。");
@@ -321,7 +698,9 @@ async fn handle_list(client: &mut DCClient) -> Result<(), String> {
             Ok(info) => {
                 println!(
                     "  ID: {}, 名前: {}, 提供者: {}",
-                    info.channel, info.name, info.supplied_by
+                    info.channel,
+                    info.name,
+                    info.supplier_info()
                 );
             }
             Err(_) => {
@@ -333,32 +712,79 @@ async fn handle_list(client: &mut DCClient) -> Result<(), String> {
     Ok(())
 }
 
-async fn handle_open(client: &mut DCClient, matches: &ArgMatches) -> Result<(), String> {
+async fn handle_open(client: &DCClient, matches: &ArgMatches) -> Result<(), CliError> {
     let name = matches.get_one::<String>("name").unwrap();
 
-    let channel_id = client
-        .open(name.clone())
-        .await
-        .map_err(|e| format!("チャンネルの作成に失敗しました: {e}"))?;
+    let channel_id = if matches.get_flag("persistent") {
+        client.open_persistent(name.clone()).await
+    } else {
+        client.open(name.clone()).await
+    }
+    .map_err(CliError::client("チャンネルの作成"))?;
 
     println!("チャンネルを開きました - ID: {channel_id}, 名前: {name}");
 
     Ok(())
 }
 
-async fn handle_info(client: &mut DCClient, matches: &ArgMatches) -> Result<(), String> {
+async fn handle_info(client: &DCClient, matches: &ArgMatches, json: bool) -> Result<(), CliError> {
     let channel_input = matches.get_one::<String>("channel").unwrap();
     let channel_id = resolve_channel_id(client, channel_input).await?;
 
     let info = client
         .channel_info(channel_id)
         .await
-        .map_err(|e| format!("チャンネル情報の取得に失敗しました: {e}"))?;
+        .map_err(CliError::client("チャンネル情報の取得"))?;
+
+    if json {
+        print_json(&info);
+        return Ok(());
+    }
 
     println!("チャンネル情報:");
     println!("  ID: {}", info.channel);
     println!("  名前: {}", info.name);
-    println!("  提供者: {}", info.supplied_by);
+    println!("  提供者: {}", info.supplier_info());
+    if let Some(supplier) = &info.supplier {
+        println!("    種別: {}", supplier.kind);
+        println!("    PID: {}", supplier.pid);
+    }
+    if let Some(schema) = &info.schema {
+        println!("  スキーマ: {schema}");
+    }
+
+    Ok(())
+}
+
+async fn handle_node(client: &DCClient, matches: &ArgMatches, json: bool) -> Result<(), CliError> {
+    let node_id = match matches.get_one::<NodeID>("node") {
+        Some(&node_id) => node_id,
+        None => client.get_node_id().await.ok_or_else(|| {
+            CliError::InvalidArgument("Node ID を取得できませんでした".to_string())
+        })?,
+    };
+
+    let info = client
+        .node_info(node_id)
+        .await
+        .map_err(CliError::client("ノード情報の取得"))?;
+
+    if json {
+        print_json(&info);
+        return Ok(());
+    }
+
+    let Some(meta) = info.meta else {
+        println!("ノード {node_id} の情報はありません");
+        return Ok(());
+    };
+
+    println!("ノード情報:");
+    println!("  ID: {}", info.node_id);
+    println!("  名前: {}", meta.name);
+    println!("  種別: {}", meta.kind);
+    println!("  ホスト: {}", meta.hostname);
+    println!("  PID: {}", meta.pid);
 
     Ok(())
 }
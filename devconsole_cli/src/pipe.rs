@@ -0,0 +1,162 @@
+//! `exec` and `pipe`: bridge a process or the CLI's own stdio to a channel.
+
+use std::{
+    io::{self, Write},
+    process::Stdio,
+    time::Duration,
+};
+
+use clap::ArgMatches;
+use devconsole::DCClient;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::{ChildStdin, Command},
+    select,
+    sync::mpsc,
+    time::Instant,
+};
+
+use crate::{CliError, attach::spawn_stdin_reader, resolve_channel_id};
+
+// Received messages queued for the process's stdin before the channel is read no further.
+const STDIN_QUEUE: usize = 64;
+
+pub async fn handle_exec(client: &DCClient, matches: &ArgMatches) -> Result<(), CliError> {
+    let channel_input = matches.get_one::<String>("channel").unwrap();
+    let command: Vec<&String> = matches.get_many::<String>("command").unwrap().collect();
+    let channel_id = resolve_channel_id(client, channel_input).await?;
+
+    let mut subscription = client
+        .subscribe(channel_id)
+        .await
+        .map_err(CliError::client("チャンネルの監視"))?;
+
+    let mut child = Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(CliError::Io)?;
+    let mut child_stdout = child.stdout.take().unwrap();
+    let (stdin_tx, stdin_rx) = mpsc::channel(STDIN_QUEUE);
+    if let Some(child_stdin) = child.stdin.take() {
+        tokio::spawn(feed_stdin(child_stdin, stdin_rx));
+    }
+
+    eprintln!("チャンネル {channel_id} と {} を接続しました。", command[0]);
+
+    let mut buf = [0u8; 4096];
+    // a message waiting for room in the stdin queue
+    let mut pending: Option<Vec<u8>> = None;
+    loop {
+        select! {
+            message = subscription.recv(), if pending.is_none() => {
+                let Some(message) = message else {
+                    eprintln!("接続が切断されたため、プロセスを終了します。");
+                    let _ = child.start_kill();
+                    break;
+                };
+                // the queue is closed once the process no longer reads its input
+                if !stdin_tx.is_closed() {
+                    pending = Some(message.as_bytes().to_vec());
+                }
+            }
+            permit = stdin_tx.reserve(), if pending.is_some() => {
+                let data = pending.take().unwrap();
+                if let Ok(permit) = permit {
+                    permit.send(data);
+                }
+            }
+            read = child_stdout.read(&mut buf) => {
+                let n = read.map_err(CliError::Io)?;
+                if n == 0 {
+                    break;
+                }
+                client
+                    .send_bin(channel_id, buf[..n].to_vec())
+                    .await
+                    .map_err(CliError::client("データの送信"))?;
+            }
+        }
+    }
+
+    // the process sees the end of its input once the queue is written out
+    drop(stdin_tx);
+    let status = child.wait().await.map_err(CliError::Io)?;
+    if !status.success() {
+        return Err(CliError::ProcessFailed(status));
+    }
+
+    Ok(())
+}
+
+/// Writes the queued messages to the process's stdin, apart from `handle_exec`'s
+/// loop so that a process not reading its input doesn't stop its output from
+/// being forwarded.
+async fn feed_stdin(mut stdin: ChildStdin, mut queue: mpsc::Receiver<Vec<u8>>) {
+    while let Some(data) = queue.recv().await {
+        let written = async {
+            stdin.write_all(&data).await?;
+            stdin.flush().await
+        };
+        // the process no longer reads its input
+        if written.await.is_err() {
+            break;
+        }
+    }
+}
+
+pub async fn handle_pipe(client: &DCClient, matches: &ArgMatches) -> Result<(), CliError> {
+    let channel_input = matches.get_one::<String>("channel").unwrap();
+    let linger = Duration::from_millis(*matches.get_one::<u64>("linger").unwrap());
+    let channel_id = resolve_channel_id(client, channel_input).await?;
+
+    let mut subscription = client
+        .subscribe(channel_id)
+        .await
+        .map_err(CliError::client("チャンネルの監視"))?;
+
+    eprintln!("チャンネル {channel_id} と標準入出力を接続しました。");
+
+    let mut stdin = spawn_stdin_reader();
+    let mut stdout = io::stdout();
+    // set once stdin is closed; received data is still written until then
+    let mut deadline: Option<Instant> = None;
+
+    loop {
+        select! {
+            message = subscription.recv() => {
+                let Some(message) = message else {
+                    break;
+                };
+                let written = stdout
+                    .write_all(message.as_bytes())
+                    .and_then(|_| stdout.flush());
+                match written {
+                    Ok(()) => {}
+                    // the reading end of the pipe is gone
+                    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+                    Err(e) => return Err(CliError::Io(e)),
+                }
+            }
+            read = stdin.recv(), if deadline.is_none() => {
+                match read {
+                    Some(read) => {
+                        let data = read.map_err(CliError::Io)?;
+                        client
+                            .send_bin(channel_id, data)
+                            .await
+                            .map_err(CliError::client("データの送信"))?;
+                    }
+                    None => deadline = Some(Instant::now() + linger),
+                }
+            }
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,181 @@
+//! `pktuart`: set up and use `dc_pktuart` bridges.
+//!
+//! A bridge decodes PktUART frames arriving on a source channel and forwards them
+//! to a destination channel as `[address, payload...]` messages; messages sent to
+//! the destination channel in that form are framed and written back to the source.
+
+use std::time::Duration;
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chrono::{Local, SecondsFormat};
+use clap::ArgMatches;
+use devconsole::DCClient;
+use devconsole_serial_protocol::pktuart::{BridgeRequest, CONTROL_CHANNEL_NAME};
+use serde_json::json;
+use tokio::time::Instant;
+
+use crate::{
+    CliError, find_supplied_by, listen::escape_binary, resolve_channel_id,
+    send::intercept_escape_sequences,
+};
+
+/// Parses a decimal or `0x`-prefixed hexadecimal address.
+pub fn parse_address(input: &str) -> Result<u8, String> {
+    let parsed = match input
+        .strip_prefix("0x")
+        .or_else(|| input.strip_prefix("0X"))
+    {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => input.parse(),
+    };
+    parsed.map_err(|_| format!("0-255 または 0x00-0xFF で指定してください: {input}"))
+}
+
+/// Parses hex bytes such as `55aa5a` or `55 aa 5a`.
+fn parse_hex(input: &str) -> Result<Vec<u8>, CliError> {
+    let digits: Vec<char> = input.chars().filter(|c| !c.is_whitespace()).collect();
+    let invalid = || CliError::InvalidArgument(format!("16進数のバイト列ではありません: {input}"));
+    if !digits.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            u8::from_str_radix(&byte, 16).map_err(|_| invalid())
+        })
+        .collect()
+}
+
+async fn handle_bridge(client: &DCClient, matches: &ArgMatches) -> Result<(), CliError> {
+    let src_input = matches.get_one::<String>("src").unwrap();
+    let dst_name = matches.get_one::<String>("dst").unwrap();
+    let wait = Duration::from_millis(*matches.get_one::<u64>("timeout").unwrap());
+
+    let src = resolve_channel_id(client, src_input).await?;
+    let control = resolve_channel_id(client, CONTROL_CHANNEL_NAME).await?;
+    let bridge = client
+        .channel_info(control)
+        .await
+        .map_err(CliError::client("チャンネル情報の取得"))?
+        .supplied_by;
+
+    let request = BridgeRequest {
+        src,
+        dst_ch_name: dst_name.clone(),
+    };
+    client
+        .send_typed(control, &request)
+        .await
+        .map_err(CliError::client("ブリッジ作成要求の送信"))?;
+
+    // dc_pktuart does not answer; the bridge is up once the node serving the control
+    // channel supplies the destination (a channel of that name from another node
+    // does not count)
+    let deadline = Instant::now() + wait;
+    let dst = loop {
+        if let Some(dst) = find_supplied_by(client, dst_name, bridge).await? {
+            break dst;
+        }
+        if Instant::now() >= deadline {
+            return Err(CliError::NoResponse(CONTROL_CHANNEL_NAME.to_string()));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+
+    println!("ブリッジを作成しました - チャンネル {src_input} ({src}) ⇔ {dst_name} ({dst})");
+
+    Ok(())
+}
+
+async fn handle_send(client: &DCClient, matches: &ArgMatches) -> Result<(), CliError> {
+    let channel_input = matches.get_one::<String>("channel").unwrap();
+    let addr = *matches.get_one::<u8>("addr").unwrap();
+    let payload = matches.get_one::<String>("payload").unwrap();
+    let payload = if matches.get_flag("hex") {
+        parse_hex(payload)?
+    } else {
+        intercept_escape_sequences(payload)
+    };
+
+    let channel_id = resolve_channel_id(client, channel_input).await?;
+
+    let len = payload.len();
+    let mut packet = vec![addr];
+    packet.extend(payload);
+    client
+        .send_bin(channel_id, packet)
+        .await
+        .map_err(CliError::client("パケットの送信"))?;
+
+    println!(
+        "チャンネル {channel_id} にパケットを送信しました - アドレス: 0x{addr:02x}, {len} バイト"
+    );
+
+    Ok(())
+}
+
+async fn handle_listen(
+    client: &DCClient,
+    matches: &ArgMatches,
+    json: bool,
+) -> Result<(), CliError> {
+    let channel_input = matches.get_one::<String>("channel").unwrap();
+    let addr_filter = matches.get_one::<u8>("addr").copied();
+    let channel_id = resolve_channel_id(client, channel_input).await?;
+
+    let mut subscription = client
+        .subscribe(channel_id)
+        .await
+        .map_err(CliError::client("チャンネルの監視"))?;
+
+    if !json {
+        println!("チャンネル {channel_id} のパケットを監視しています。Ctrl+C で終了します。");
+    }
+
+    while let Some(message) = subscription.recv().await {
+        let Some((&addr, payload)) = message.as_bytes().split_first() else {
+            continue;
+        };
+        if addr_filter.is_some_and(|filter| filter != addr) {
+            continue;
+        }
+
+        let time = Local::now();
+        if json {
+            let record = json!({
+                "time": time.to_rfc3339_opts(SecondsFormat::Millis, false),
+                "from": message.from(),
+                "addr": addr,
+                "data": BASE64.encode(payload),
+            });
+            println!("{record}");
+            continue;
+        }
+
+        let hex: Vec<String> = payload.iter().map(|b| format!("{b:02x}")).collect();
+        println!(
+            "{} [0x{addr:02x}] {} バイト: {}  |{}|",
+            time.format("%H:%M:%S%.3f"),
+            payload.len(),
+            hex.join(" "),
+            escape_binary(payload).replace('\n', r"\n")
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn handle_pktuart(
+    client: &DCClient,
+    matches: &ArgMatches,
+    json: bool,
+) -> Result<(), CliError> {
+    match matches.subcommand() {
+        Some(("bridge", sub_matches)) => handle_bridge(client, sub_matches).await,
+        Some(("send", sub_matches)) => handle_send(client, sub_matches).await,
+        Some(("listen", sub_matches)) => handle_listen(client, sub_matches, json).await,
+        _ => unreachable!("pktuart requires a subcommand"),
+    }
+}
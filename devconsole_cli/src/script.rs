@@ -0,0 +1,703 @@
+//! `script run`: expect-style test scripts written in TOML or YAML.
+//!
+//! A script is a list of test cases, each a list of steps run in order:
+//! sending data, waiting for received data to match a pattern, capturing
+//! values from the match and asserting on them. Every channel used by the
+//! script is subscribed before the first step, so nothing sent in reply is
+//! missed.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    path::Path,
+    time::Duration,
+};
+
+use clap::ArgMatches;
+use devconsole::{ChannelID, DCClient, Subscription};
+use futures_util::{FutureExt, StreamExt};
+use regex::bytes::Regex;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::time::Instant;
+
+use crate::{CliError, listen::escape_binary, print_json, resolve_channel_id};
+
+const DEFAULT_TIMEOUT_MS: u64 = 2000;
+
+fn default_timeout() -> u64 {
+    DEFAULT_TIMEOUT_MS
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Script {
+    name: Option<String>,
+    /// Default timeout of `expect` steps, in milliseconds.
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+    tests: Vec<TestCase>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TestCase {
+    name: String,
+    /// Each step is a table with a single key, such as `{ sleep = 100 }`.
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Step {
+    Send(SendStep),
+    Expect(ExpectStep),
+    Assert(AssertStep),
+    /// Waits for the given number of milliseconds.
+    Sleep(u64),
+    /// Discards the data received on a channel so far.
+    Flush(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SendStep {
+    channel: String,
+    text: Option<String>,
+    /// Bytes in hex, such as `55 aa 5a`.
+    hex: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExpectStep {
+    channel: String,
+    regex: Option<String>,
+    /// Bytes in hex where `??` matches any byte.
+    bytes: Option<String>,
+    timeout: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AssertStep {
+    value: String,
+    equals: Option<String>,
+    matches: Option<String>,
+}
+
+impl Step {
+    fn channel(&self) -> Option<&str> {
+        match self {
+            Step::Send(step) => Some(&step.channel),
+            Step::Expect(step) => Some(&step.channel),
+            Step::Flush(channel) => Some(channel),
+            Step::Assert(_) | Step::Sleep(_) => None,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Step::Send(step) => format!("send {}", step.channel),
+            Step::Expect(step) => format!("expect {}", step.channel),
+            Step::Assert(step) => format!("assert {}", step.value),
+            Step::Sleep(ms) => format!("sleep {ms}"),
+            Step::Flush(channel) => format!("flush {channel}"),
+        }
+    }
+}
+
+/// Parses hex bytes, where `??` stands for any byte if `wildcard` is set.
+fn parse_hex(input: &str, wildcard: bool) -> Result<Vec<Option<u8>>, String> {
+    let digits: Vec<char> = input.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("16進数のバイト列ではありません: {input}"));
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            if wildcard && byte == "??" {
+                return Ok(None);
+            }
+            u8::from_str_radix(&byte, 16)
+                .map(Some)
+                .map_err(|_| format!("16進数のバイト列ではありません: {input}"))
+        })
+        .collect()
+}
+
+/// Builds a byte regex matching the hex pattern of an `expect` step.
+fn bytes_regex(pattern: &str) -> Result<Regex, String> {
+    let mut regex = String::from("(?s-u)");
+    for byte in parse_hex(pattern, true)? {
+        match byte {
+            Some(b) => write!(regex, r"\x{b:02x}").unwrap(),
+            None => regex.push('.'),
+        }
+    }
+    Regex::new(&regex).map_err(|e| e.to_string())
+}
+
+struct Channel {
+    id: ChannelID,
+    subscription: Subscription,
+    /// Received data not consumed by an `expect` step yet.
+    buffer: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Failure {
+    /// 1-based index of the failed step.
+    step: usize,
+    description: String,
+    message: String,
+}
+
+#[derive(Debug)]
+struct TestResult {
+    name: String,
+    duration: Duration,
+    failure: Option<Failure>,
+}
+
+struct Runner<'a> {
+    client: &'a DCClient,
+    channels: HashMap<String, Channel>,
+    variables: BTreeMap<String, String>,
+    default_timeout: Duration,
+}
+
+/// Replaces `${name}` with the value of the variable `name`.
+fn substitute(input: &str, variables: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut output = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        output.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("変数の '}}' がありません: {input}"));
+        };
+        let name = &rest[start + 2..start + end];
+        let Some(value) = variables.get(name) else {
+            return Err(format!("未定義の変数です: {name}"));
+        };
+        output.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+impl Runner<'_> {
+    fn substitute(&self, input: &str) -> Result<String, String> {
+        substitute(input, &self.variables)
+    }
+
+    fn channel(&mut self, name: &str) -> &mut Channel {
+        // every channel is subscribed before the steps run
+        self.channels.get_mut(name).unwrap()
+    }
+
+    async fn run_step(&mut self, step: &Step) -> Result<(), String> {
+        match step {
+            Step::Send(step) => self.send(step).await,
+            Step::Expect(step) => self.expect(step).await,
+            Step::Assert(step) => self.assert(step),
+            Step::Sleep(ms) => {
+                tokio::time::sleep(Duration::from_millis(*ms)).await;
+                Ok(())
+            }
+            Step::Flush(channel) => {
+                let channel = self.channel(channel);
+                // take what has already arrived without waiting for more
+                while let Some(Some(_)) = channel.subscription.next().now_or_never() {}
+                channel.buffer.clear();
+                Ok(())
+            }
+        }
+    }
+
+    async fn send(&mut self, step: &SendStep) -> Result<(), String> {
+        let data = match (&step.text, &step.hex) {
+            (Some(text), None) => self.substitute(text)?.into_bytes(),
+            (None, Some(hex)) => parse_hex(&self.substitute(hex)?, false)?
+                .into_iter()
+                .flatten()
+                .collect(),
+            _ => return Err("send には text か hex のどちらか一方を指定してください".to_string()),
+        };
+
+        let client = self.client;
+        let id = self.channel(&step.channel).id;
+        client
+            .send_bin(id, data)
+            .await
+            .map_err(|e| format!("送信に失敗しました: {e}"))
+    }
+
+    async fn expect(&mut self, step: &ExpectStep) -> Result<(), String> {
+        let (regex, pattern) = match (&step.regex, &step.bytes) {
+            (Some(regex), None) => {
+                let regex = self.substitute(regex)?;
+                (Regex::new(&regex).map_err(|e| e.to_string())?, regex)
+            }
+            (None, Some(bytes)) => {
+                let bytes = self.substitute(bytes)?;
+                (bytes_regex(&bytes)?, bytes)
+            }
+            _ => {
+                return Err(
+                    "expect には regex か bytes のどちらか一方を指定してください".to_string(),
+                );
+            }
+        };
+        let timeout = step
+            .timeout
+            .map(Duration::from_millis)
+            .unwrap_or(self.default_timeout);
+        let deadline = Instant::now() + timeout;
+
+        let channel = self.channel(&step.channel);
+        let captured = loop {
+            if let Some(captures) = regex.captures(&channel.buffer) {
+                let end = captures.get(0).unwrap().end();
+                let captured: Vec<(String, String)> = regex
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|name| {
+                        let value = captures.name(name)?;
+                        Some((
+                            name.to_string(),
+                            String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                        ))
+                    })
+                    .collect();
+                channel.buffer.drain(..end);
+                break captured;
+            }
+
+            match tokio::time::timeout_at(deadline, channel.subscription.recv()).await {
+                Ok(Some(message)) => channel.buffer.extend_from_slice(message.as_bytes()),
+                Ok(None) => return Err("接続が切断されました".to_string()),
+                Err(_) => {
+                    return Err(format!(
+                        "{} ms 以内に '{pattern}' に一致するデータを受信しませんでした (受信済み: \"{}\")",
+                        timeout.as_millis(),
+                        escape_binary(&channel.buffer).replace('\n', r"\n")
+                    ));
+                }
+            }
+        };
+
+        self.variables.extend(captured);
+        Ok(())
+    }
+
+    fn assert(&self, step: &AssertStep) -> Result<(), String> {
+        let value = self.substitute(&step.value)?;
+        if let Some(expected) = &step.equals {
+            let expected = self.substitute(expected)?;
+            if value != expected {
+                return Err(format!("'{value}' は '{expected}' と一致しません"));
+            }
+        }
+        if let Some(pattern) = &step.matches {
+            let pattern = self.substitute(pattern)?;
+            let regex = regex::Regex::new(&pattern).map_err(|e| e.to_string())?;
+            if !regex.is_match(&value) {
+                return Err(format!("'{value}' は '{pattern}' に一致しません"));
+            }
+        }
+        Ok(())
+    }
+
+    async fn run_test(&mut self, test: &TestCase) -> TestResult {
+        // data left unmatched by the previous test is not matched by this one
+        for channel in self.channels.values_mut() {
+            channel.buffer.clear();
+        }
+        let started = Instant::now();
+        let mut failure = None;
+        for (index, step) in test.steps.iter().enumerate() {
+            if let Err(message) = self.run_step(step).await {
+                failure = Some(Failure {
+                    step: index + 1,
+                    description: step.describe(),
+                    message,
+                });
+                break;
+            }
+        }
+
+        TestResult {
+            name: test.name.clone(),
+            duration: started.elapsed(),
+            failure,
+        }
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn junit_report(suite: &str, results: &[TestResult]) -> String {
+    let failures = results.iter().filter(|r| r.failure.is_some()).count();
+    let total: f64 = results.iter().map(|r| r.duration.as_secs_f64()).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        xml,
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{failures}\" time=\"{total:.3}\">",
+        escape_xml(suite),
+        results.len()
+    )
+    .unwrap();
+    for result in results {
+        let attributes = format!(
+            "name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape_xml(&result.name),
+            escape_xml(suite),
+            result.duration.as_secs_f64()
+        );
+        match &result.failure {
+            None => writeln!(xml, "  <testcase {attributes}/>").unwrap(),
+            Some(failure) => {
+                let message = format!(
+                    "ステップ {} ({}): {}",
+                    failure.step, failure.description, failure.message
+                );
+                writeln!(
+                    xml,
+                    "  <testcase {attributes}>\n    <failure message=\"{0}\">{0}</failure>\n  </testcase>",
+                    escape_xml(&message)
+                )
+                .unwrap();
+            }
+        }
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+fn json_report(
+    suite: &str,
+    results: &[TestResult],
+    variables: &BTreeMap<String, String>,
+) -> serde_json::Value {
+    let tests: Vec<serde_json::Value> = results
+        .iter()
+        .map(|result| {
+            json!({
+                "name": result.name,
+                "passed": result.failure.is_none(),
+                "duration_ms": result.duration.as_millis() as u64,
+                "failure": result.failure.as_ref().map(|failure| json!({
+                    "step": failure.step,
+                    "description": failure.description,
+                    "message": failure.message,
+                })),
+            })
+        })
+        .collect();
+    let failed = results.iter().filter(|r| r.failure.is_some()).count();
+
+    json!({
+        "name": suite,
+        "passed": results.len() - failed,
+        "failed": failed,
+        "tests": tests,
+        "variables": variables,
+    })
+}
+
+/// Parses a script, as YAML if `path` ends in `.yaml` or `.yml` and as TOML otherwise.
+fn parse_script(path: &str, source: &str) -> Result<Script, String> {
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        // steps are single-key maps as in TOML rather than YAML tags
+        Some("yaml" | "yml") => serde_norway::with::singleton_map_recursive::deserialize(
+            serde_norway::Deserializer::from_str(source),
+        )
+        .map_err(|e| e.to_string()),
+        _ => toml::from_str(source).map_err(|e| e.to_string()),
+    }
+}
+
+fn parse_variable(input: &str) -> Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((key, value)) => Ok((key.to_string(), value.to_string())),
+        None => Err(format!("KEY=VALUE の形式で指定してください: {input}")),
+    }
+}
+
+async fn handle_run(client: &DCClient, matches: &ArgMatches, json: bool) -> Result<(), CliError> {
+    let path = matches.get_one::<String>("file").unwrap();
+    let source = tokio::fs::read_to_string(path)
+        .await
+        .map_err(CliError::Io)?;
+    let script = parse_script(path, &source).map_err(CliError::Script)?;
+    let suite = script.name.clone().unwrap_or_else(|| path.clone());
+
+    let mut variables = BTreeMap::new();
+    for input in matches.get_many::<String>("var").into_iter().flatten() {
+        let (key, value) = parse_variable(input).map_err(CliError::InvalidArgument)?;
+        variables.insert(key, value);
+    }
+
+    let mut channels = HashMap::new();
+    for name in script
+        .tests
+        .iter()
+        .flat_map(|test| &test.steps)
+        .filter_map(Step::channel)
+    {
+        if channels.contains_key(name) {
+            continue;
+        }
+        let id = resolve_channel_id(client, name).await?;
+        let subscription = client
+            .subscribe(id)
+            .await
+            .map_err(CliError::client("チャンネルの監視"))?;
+        channels.insert(
+            name.to_string(),
+            Channel {
+                id,
+                subscription,
+                buffer: Vec::new(),
+            },
+        );
+    }
+
+    let mut runner = Runner {
+        client,
+        channels,
+        variables,
+        default_timeout: Duration::from_millis(script.timeout),
+    };
+
+    if !json {
+        println!("スクリプト: {suite}");
+    }
+    let mut results = Vec::new();
+    for test in &script.tests {
+        let result = runner.run_test(test).await;
+        if !json {
+            let secs = result.duration.as_secs_f64();
+            match &result.failure {
+                None => println!("  ✔ {} ({secs:.2} 秒)", result.name),
+                Some(failure) => {
+                    println!("  ✘ {} ({secs:.2} 秒)", result.name);
+                    println!(
+                        "      ステップ {} ({}): {}",
+                        failure.step, failure.description, failure.message
+                    );
+                }
+            }
+        }
+        results.push(result);
+    }
+
+    let failed = results.iter().filter(|r| r.failure.is_some()).count();
+    if json {
+        print_json(&json_report(&suite, &results, &runner.variables));
+    } else {
+        println!("結果: {} 件成功, {failed} 件失敗", results.len() - failed);
+    }
+    if let Some(junit) = matches.get_one::<String>("junit") {
+        tokio::fs::write(junit, junit_report(&suite, &results))
+            .await
+            .map_err(CliError::Io)?;
+    }
+
+    if failed > 0 {
+        return Err(CliError::ScriptFailed {
+            failed,
+            total: results.len(),
+        });
+    }
+
+    Ok(())
+}
+
+pub async fn handle_script(
+    client: &DCClient,
+    matches: &ArgMatches,
+    json: bool,
+) -> Result<(), CliError> {
+    match matches.subcommand() {
+        Some(("run", sub_matches)) => handle_run(client, sub_matches, json).await,
+        _ => unreachable!("script requires a subcommand"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_with_and_without_wildcards() {
+        assert_eq!(
+            parse_hex("55 aa5A\t01", false),
+            Ok(vec![Some(0x55), Some(0xaa), Some(0x5a), Some(0x01)])
+        );
+        assert_eq!(
+            parse_hex("55 ?? 00", true),
+            Ok(vec![Some(0x55), None, Some(0x00)])
+        );
+        assert!(parse_hex("55 ?? 00", false).is_err());
+        assert_eq!(parse_hex("", false), Ok(vec![]));
+    }
+
+    #[test]
+    fn rejects_odd_digit_counts_and_non_hex() {
+        assert!(parse_hex("5", false).is_err());
+        assert!(parse_hex("55 a", false).is_err());
+        assert!(parse_hex("55 ?", true).is_err());
+        assert!(parse_hex("zz", false).is_err());
+        // half a wildcard is not one
+        assert!(parse_hex("5?", true).is_err());
+    }
+
+    #[test]
+    fn bytes_regex_matches_any_byte_for_wildcards() {
+        let regex = bytes_regex("55 ?? 0a").unwrap();
+        assert!(regex.is_match(&[0x55, 0x00, 0x0a]));
+        // newlines and non-UTF-8 bytes are bytes like any other
+        assert!(regex.is_match(&[0x55, b'\n', 0x0a]));
+        assert!(regex.is_match(&[0xff, 0x55, 0xff, 0x0a]));
+        assert!(!regex.is_match(&[0x55, 0x0a]));
+        assert!(!regex.is_match(&[0x55, 0x00, 0x0b]));
+
+        // regex metacharacters are matched literally
+        let regex = bytes_regex("2e 2a").unwrap();
+        assert!(regex.is_match(b".*"));
+        assert!(!regex.is_match(b"ab"));
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let variables = BTreeMap::from([
+            ("ver".to_string(), "1.2".to_string()),
+            ("n".to_string(), "3".to_string()),
+        ]);
+        assert_eq!(
+            substitute("v${ver}, ${n}${n}", &variables),
+            Ok("v1.2, 33".to_string())
+        );
+        assert_eq!(
+            substitute("no variables {}", &variables),
+            Ok("no variables {}".to_string())
+        );
+        assert!(
+            substitute("${missing}", &variables)
+                .unwrap_err()
+                .contains("missing")
+        );
+        assert!(substitute("v${ver", &variables).is_err());
+        assert!(substitute("${", &variables).is_err());
+    }
+
+    #[test]
+    fn junit_report_escapes_names_and_messages() {
+        let results = [
+            TestResult {
+                name: "ok <1>".to_string(),
+                duration: Duration::from_millis(1500),
+                failure: None,
+            },
+            TestResult {
+                name: "\"quoted\" & more".to_string(),
+                duration: Duration::ZERO,
+                failure: Some(Failure {
+                    step: 2,
+                    description: "expect MCU".to_string(),
+                    message: "'<tag>' & \"x\"".to_string(),
+                }),
+            },
+        ];
+        let xml = junit_report("suite & <co>", &results);
+
+        assert!(xml.contains(
+            "<testsuite name=\"suite &amp; &lt;co&gt;\" tests=\"2\" failures=\"1\" time=\"1.500\">"
+        ));
+        assert!(xml.contains(
+            "<testcase name=\"ok &lt;1&gt;\" classname=\"suite &amp; &lt;co&gt;\" time=\"1.500\"/>"
+        ));
+        assert!(xml.contains("name=\"&quot;quoted&quot; &amp; more\""));
+        let message = "ステップ 2 (expect MCU): '&lt;tag&gt;' &amp; &quot;x&quot;";
+        assert!(xml.contains(&format!(
+            "<failure message=\"{message}\">{message}</failure>"
+        )));
+        assert!(!xml.contains("<tag>"));
+    }
+
+    #[test]
+    fn parses_the_documented_script() {
+        let source = r#"
+name = "MCU smoke test"
+timeout = 2000
+
+[[tests]]
+name = "version"
+steps = [
+    { flush = "MCU" },
+    { send = { channel = "MCU", text = "version\r\n" } },
+    { expect = { channel = "MCU", regex = 'v(?P<ver>\d+\.\d+)', timeout = 3000 } },
+    { assert = { value = "${ver}", equals = "${expected}" } },
+]
+
+[[tests]]
+name = "packet"
+steps = [
+    { send = { channel = "MCU", hex = "55 aa 5a 01" } },
+    { expect = { channel = "MCU", bytes = "55 aa 5a ?? 00" } },
+    { sleep = 100 },
+]
+"#;
+        let script: Script = toml::from_str(source).unwrap();
+        assert_eq!(script.tests.len(), 2);
+        let steps: Vec<String> = script.tests[0].steps.iter().map(Step::describe).collect();
+        assert_eq!(
+            steps,
+            ["flush MCU", "send MCU", "expect MCU", "assert ${ver}"]
+        );
+        let Step::Send(send) = &script.tests[0].steps[1] else {
+            panic!("not a send step");
+        };
+        assert_eq!(send.text.as_deref(), Some("version\r\n"));
+        assert!(matches!(script.tests[1].steps[2], Step::Sleep(100)));
+
+        let unknown = "[[tests]]\nname = \"x\"\nsteps = [{ wait = 1 }]\n";
+        assert!(toml::from_str::<Script>(unknown).is_err());
+    }
+
+    #[test]
+    fn picks_the_format_by_extension() {
+        let source = r#"
+name: MCU smoke test
+tests:
+  - name: version
+    steps:
+      - flush: MCU
+      - send: { channel: MCU, text: "version\r\n" }
+      - expect: { channel: MCU, regex: 'v(?P<ver>\d+\.\d+)', timeout: 3000 }
+      - sleep: 100
+"#;
+        let script = parse_script("test.yaml", source).unwrap();
+        assert_eq!(script.timeout, DEFAULT_TIMEOUT_MS);
+        let steps: Vec<String> = script.tests[0].steps.iter().map(Step::describe).collect();
+        assert_eq!(steps, ["flush MCU", "send MCU", "expect MCU", "sleep 100"]);
+        let Step::Send(send) = &script.tests[0].steps[1] else {
+            panic!("not a send step");
+        };
+        assert_eq!(send.text.as_deref(), Some("version\r\n"));
+
+        assert!(parse_script("test.yml", source).is_ok());
+        assert!(parse_script("test.toml", source).is_err());
+    }
+}
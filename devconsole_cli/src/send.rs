@@ -0,0 +1,200 @@
+//! `send`: send a message, or stream stdin or a file, to a channel.
+
+use std::time::Duration;
+
+use clap::ArgMatches;
+use devconsole::{ChannelID, DCClient};
+use tokio::{
+    fs::File,
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader},
+    time::Instant,
+};
+
+use crate::{CliError, resolve_channel_id};
+
+pub(crate) fn intercept_escape_sequences(input: &str) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c as u8);
+            continue;
+        }
+        let next_char = match chars.next() {
+            Some(nc) => nc,
+            None => {
+                output.push(b'\\');
+                break;
+            }
+        };
+        match next_char {
+            'n' => output.push(b'\n'),
+            'r' => output.push(b'\r'),
+            't' => output.push(b'\t'),
+            'e' => output.push(b'\x1b'),
+            '0' => output.push(b'\0'),
+            'x' => {
+                let hex1 = chars.next();
+                let hex2 = chars.next();
+                if let (Some(h1), Some(h2)) = (hex1, hex2) {
+                    if let (Some(d1), Some(d2)) = (h1.to_digit(16), h2.to_digit(16)) {
+                        output.push(((d1 << 4) | d2) as u8);
+                    } else {
+                        output.push(b'\\');
+                        output.push(b'x');
+                        output.push(h1 as u8);
+                        output.push(h2 as u8);
+                    }
+                }
+            }
+            _ => {
+                output.push(b'\\');
+                output.push(next_char as u8);
+            }
+        }
+    }
+
+    output
+}
+
+/// Paces chunks by a fixed delay and/or a byte rate.
+struct Pacer {
+    delay: Option<Duration>,
+    /// Bytes per second.
+    rate: Option<u64>,
+    started: Instant,
+    sent: u64,
+}
+
+impl Pacer {
+    /// Waits before sending a chunk of `len` bytes.
+    async fn wait(&mut self, len: usize) {
+        if self.sent > 0
+            && let Some(delay) = self.delay
+        {
+            tokio::time::sleep(delay).await;
+        }
+        if let Some(rate) = self.rate {
+            // a chunk may leave once the bytes before it fit in the rate
+            let due = self.started + Duration::from_secs_f64(self.sent as f64 / rate as f64);
+            tokio::time::sleep_until(due).await;
+        }
+        self.sent += len as u64;
+    }
+}
+
+/// Reads until `buf` is full or the input ends, returning the number of bytes read.
+async fn read_block(reader: &mut (impl AsyncRead + Unpin), buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// Streams `input` to the channel as text lines or binary blocks, returning
+/// the number of chunks and bytes sent.
+async fn send_stream(
+    client: &DCClient,
+    channel_id: ChannelID,
+    input: impl AsyncRead + Unpin,
+    matches: &ArgMatches,
+) -> Result<(usize, u64), CliError> {
+    let mut pacer = Pacer {
+        delay: matches
+            .get_one::<u64>("delay")
+            .map(|&ms| Duration::from_millis(ms)),
+        rate: matches.get_one::<u64>("rate").copied(),
+        started: Instant::now(),
+        sent: 0,
+    };
+    let mut chunks = 0;
+
+    if matches.get_flag("binary") {
+        let mut input = input;
+        let chunk_size = *matches.get_one::<usize>("chunk-size").unwrap();
+        if chunk_size == 0 {
+            return Err(CliError::InvalidArgument(
+                "--chunk-size には 1 以上を指定してください".to_string(),
+            ));
+        }
+        let mut buf = vec![0u8; chunk_size];
+        loop {
+            let n = read_block(&mut input, &mut buf)
+                .await
+                .map_err(CliError::Io)?;
+            if n == 0 {
+                break;
+            }
+            pacer.wait(n).await;
+            client
+                .send_bin(channel_id, buf[..n].to_vec())
+                .await
+                .map_err(CliError::client("データの送信"))?;
+            chunks += 1;
+        }
+    } else {
+        let mut input = BufReader::new(input);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let n = input
+                .read_until(b'\n', &mut line)
+                .await
+                .map_err(CliError::Io)?;
+            if n == 0 {
+                break;
+            }
+            pacer.wait(n).await;
+            client
+                .send(channel_id, String::from_utf8_lossy(&line).into_owned())
+                .await
+                .map_err(CliError::client("データの送信"))?;
+            chunks += 1;
+        }
+    }
+
+    Ok((chunks, pacer.sent))
+}
+
+pub async fn handle_send(client: &DCClient, matches: &ArgMatches) -> Result<(), CliError> {
+    let channel_input = matches.get_one::<String>("channel").unwrap();
+    let channel_id = resolve_channel_id(client, channel_input).await?;
+
+    let streamed = if matches.get_flag("stdin") {
+        Some(send_stream(client, channel_id, io::stdin(), matches).await?)
+    } else if let Some(path) = matches.get_one::<String>("file") {
+        let file = File::open(path).await.map_err(CliError::Io)?;
+        Some(send_stream(client, channel_id, file, matches).await?)
+    } else {
+        None
+    };
+    if let Some((chunks, bytes)) = streamed {
+        println!("チャンネル {channel_id} に {chunks} 件 ({bytes} バイト) を送信しました");
+        return Ok(());
+    }
+
+    let message = matches.get_one::<String>("message").unwrap();
+    let is_binary = matches.get_flag("binary");
+
+    if is_binary {
+        client
+            .send_bin(channel_id, intercept_escape_sequences(message))
+            .await
+            .map_err(CliError::client("メッセージの送信"))?;
+    } else {
+        client
+            .send(channel_id, message.clone())
+            .await
+            .map_err(CliError::client("メッセージの送信"))?;
+    }
+
+    println!("チャンネル {channel_id} にメッセージを送信しました: {message}");
+
+    Ok(())
+}
@@ -0,0 +1,245 @@
+//! `serial`: control the serial monitor through its request channel.
+
+use std::time::Duration;
+
+use chrono::{Local, SecondsFormat};
+use clap::ArgMatches;
+use devconsole::{ChannelID, DCClient, TypedSubscription};
+use devconsole_serial_protocol::{
+    CHANNEL_NAME, REQUEST_CHANNEL_NAME, SerialEvent, SerialPort, SerialRequest,
+};
+use serde_json::json;
+use tokio::time::{Instant, timeout};
+
+use crate::{
+    CliError, find_supplied_by, listen::escape_binary, print_json, resolve_channel_id,
+    send::intercept_escape_sequences,
+};
+
+/// Subscribes to the serial monitor's events.
+async fn subscribe(client: &DCClient) -> Result<TypedSubscription<SerialEvent>, CliError> {
+    let channel_id = resolve_channel_id(client, CHANNEL_NAME).await?;
+    client
+        .subscribe_typed::<SerialEvent>(channel_id)
+        .await
+        .map_err(CliError::client("シリアルモニターの監視"))
+}
+
+/// Resolves the channel the serial monitor takes requests from.
+async fn request_channel(client: &DCClient) -> Result<ChannelID, CliError> {
+    resolve_channel_id(client, REQUEST_CHANNEL_NAME).await
+}
+
+async fn request(
+    client: &DCClient,
+    channel_id: ChannelID,
+    request: &SerialRequest,
+) -> Result<(), CliError> {
+    client
+        .send_typed(channel_id, request)
+        .await
+        .map_err(CliError::client("シリアルモニターへの要求の送信"))
+}
+
+/// Asks for the monitored ports and waits for the answer.
+async fn list_ports(
+    client: &DCClient,
+    channel_id: ChannelID,
+    events: &mut TypedSubscription<SerialEvent>,
+    wait: Duration,
+) -> Result<Vec<SerialPort>, CliError> {
+    request(client, channel_id, &SerialRequest::List).await?;
+
+    let answer = async {
+        while let Some(event) = events.recv().await {
+            if let Ok(SerialEvent::Ports { ports }) = event {
+                return Some(ports);
+            }
+        }
+        None
+    };
+    match timeout(wait, answer).await {
+        Ok(Some(ports)) => Ok(ports),
+        _ => Err(CliError::NoResponse(CHANNEL_NAME.to_string())),
+    }
+}
+
+async fn vport_name(client: &DCClient, vport: ChannelID) -> String {
+    match client.channel_info(vport).await {
+        Ok(info) => format!("{} ({vport})", info.name),
+        Err(_) => vport.to_string(),
+    }
+}
+
+async fn handle_list(client: &DCClient, matches: &ArgMatches, json: bool) -> Result<(), CliError> {
+    let wait = Duration::from_millis(*matches.get_one::<u64>("timeout").unwrap());
+    let mut events = subscribe(client).await?;
+    let channel_id = request_channel(client).await?;
+    let ports = list_ports(client, channel_id, &mut events, wait).await?;
+
+    if json {
+        print_json(&ports);
+        return Ok(());
+    }
+
+    if ports.is_empty() {
+        println!("監視中のシリアルポートはありません。");
+        return Ok(());
+    }
+
+    println!("監視中のシリアルポート:");
+    for port in &ports {
+        match port.vport {
+            Some(vport) => println!(
+                "  {} (仮想ポート: {})",
+                port.path,
+                vport_name(client, vport).await
+            ),
+            None => println!("  {}", port.path),
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_open_vport(client: &DCClient, matches: &ArgMatches) -> Result<(), CliError> {
+    let path = matches.get_one::<String>("path").unwrap();
+    let name = matches.get_one::<String>("name").unwrap();
+    let wait = Duration::from_millis(*matches.get_one::<u64>("timeout").unwrap());
+    let mut events = subscribe(client).await?;
+    let channel_id = request_channel(client).await?;
+    let monitor = client
+        .channel_info(channel_id)
+        .await
+        .map_err(CliError::client("チャンネル情報の取得"))?
+        .supplied_by;
+
+    request(
+        client,
+        channel_id,
+        &SerialRequest::OpenVPort {
+            path: path.clone(),
+            channel_name: name.clone(),
+        },
+    )
+    .await?;
+
+    let deadline = Instant::now() + wait;
+    let ports = list_ports(client, channel_id, &mut events, wait).await?;
+    // the port is open once the serial monitor supplies its channel (a channel of
+    // that name from another node does not count)
+    let vport = loop {
+        if let Some(vport) = find_supplied_by(client, name, monitor).await? {
+            break vport;
+        }
+        if Instant::now() >= deadline {
+            return Err(CliError::NoResponse(REQUEST_CHANNEL_NAME.to_string()));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    println!("仮想ポートを開きました - {path} → チャンネル {name} ({vport})");
+    if !ports.iter().any(|port| &port.path == path) {
+        eprintln!(
+            "{path} は現在接続されていません。接続されるとチャンネル {name} に転送されます。"
+        );
+    }
+
+    Ok(())
+}
+
+async fn handle_write(client: &DCClient, matches: &ArgMatches) -> Result<(), CliError> {
+    let path = matches.get_one::<String>("path").unwrap();
+    let data = intercept_escape_sequences(matches.get_one::<String>("data").unwrap());
+    let channel_id = request_channel(client).await?;
+
+    let len = data.len();
+    request(
+        client,
+        channel_id,
+        &SerialRequest::Data {
+            path: path.clone(),
+            data,
+        },
+    )
+    .await?;
+
+    println!("{path} に {len} バイトを送信しました");
+
+    Ok(())
+}
+
+fn event_path(event: &SerialEvent) -> Option<&str> {
+    match event {
+        SerialEvent::Opened { path }
+        | SerialEvent::Line { path, .. }
+        | SerialEvent::Closed { path } => Some(path),
+        SerialEvent::Ports { .. } => None,
+    }
+}
+
+async fn handle_watch(client: &DCClient, matches: &ArgMatches, json: bool) -> Result<(), CliError> {
+    let paths: Vec<&String> = matches
+        .get_many::<String>("path")
+        .map(|paths| paths.collect())
+        .unwrap_or_default();
+    let mut events = subscribe(client).await?;
+
+    if !json {
+        println!("シリアルモニターのイベントを監視しています。Ctrl+C で終了します。");
+    }
+
+    while let Some(event) = events.recv().await {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("シリアルモニターのイベントを解釈できません: {e}");
+                continue;
+            }
+        };
+        if !paths.is_empty()
+            && !event_path(&event).is_some_and(|path| paths.iter().any(|p| *p == path))
+        {
+            continue;
+        }
+
+        let time = Local::now();
+        if json {
+            let record = json!({
+                "time": time.to_rfc3339_opts(SecondsFormat::Millis, false),
+                "event": event,
+            });
+            println!("{record}");
+            continue;
+        }
+
+        let time = time.format("%H:%M:%S%.3f");
+        match &event {
+            SerialEvent::Opened { path } => println!("{time} {path}: 接続されました"),
+            SerialEvent::Closed { path } => println!("{time} {path}: 切断されました"),
+            SerialEvent::Line { path, line } => {
+                let line = line.strip_suffix(b"\n").unwrap_or(line);
+                println!("{time} {path}> {}", escape_binary(line))
+            }
+            SerialEvent::Ports { ports } => {
+                let paths: Vec<&str> = ports.iter().map(|port| port.path.as_str()).collect();
+                println!("{time} 監視中のポート: {}", paths.join(", "));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn handle_serial(
+    client: &DCClient,
+    matches: &ArgMatches,
+    json: bool,
+) -> Result<(), CliError> {
+    match matches.subcommand() {
+        Some(("list", sub_matches)) => handle_list(client, sub_matches, json).await,
+        Some(("open-vport", sub_matches)) => handle_open_vport(client, sub_matches).await,
+        Some(("write", sub_matches)) => handle_write(client, sub_matches).await,
+        Some(("watch", sub_matches)) => handle_watch(client, sub_matches, json).await,
+        _ => unreachable!("serial requires a subcommand"),
+    }
+}
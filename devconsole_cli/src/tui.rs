@@ -0,0 +1,903 @@
+//! `tui`: full-screen dashboard of every channel and node.
+//!
+//! Every channel is subscribed as soon as it shows up, so the scrollback of a
+//! channel covers everything received since the dashboard was started. The
+//! serial monitor's channel and `dc_pktuart` bridge channels are decoded with
+//! their protocols, and the input line builds their requests.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque, btree_map},
+    io,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local};
+use devconsole::{ChannelID, ChannelInfo, DCClient, Message, NodeID, NodeMeta, Subscription};
+use devconsole_serial_protocol::{
+    CHANNEL_NAME, REQUEST_CHANNEL_NAME, SerialEvent, SerialRequest,
+    pktuart::CONTROL_CHANNEL_NAME as PKTUART_CONTROL_CHANNEL,
+};
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+};
+use tokio::{select, sync::mpsc, task::JoinHandle, time::timeout};
+
+use crate::{
+    CliError,
+    listen::{escape_binary, hexdump},
+    pktuart::parse_address,
+    send::intercept_escape_sequences,
+    watch::{same_channel, snapshot},
+};
+
+/// Entries kept per channel; older ones are discarded.
+const SCROLLBACK: usize = 5000;
+
+/// Interval between refreshes of the channel and node lists.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long one refresh may take before it is given up.
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How the data of a channel is shown and how the input line is sent to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decoder {
+    Raw,
+    /// The serial monitor's `SerialEvent`s; input is `<path> <data>`, sent to its
+    /// request channel.
+    Serial,
+    /// Requests to the serial monitor; input is `<path> <data>`.
+    SerialRequests,
+    /// `[address, payload...]` packets of a `dc_pktuart` bridge; input is `<addr> <payload>`.
+    PktUart,
+}
+
+impl Decoder {
+    fn of(info: &ChannelInfo) -> Self {
+        if info.name == CHANNEL_NAME {
+            return Decoder::Serial;
+        }
+        if info.name == REQUEST_CHANNEL_NAME {
+            return Decoder::SerialRequests;
+        }
+        // dc_pktuart supplies the destination channels of its bridges
+        match &info.supplier {
+            Some(meta)
+                if meta.kind == "bridge"
+                    && meta.name == "pktuart"
+                    && info.name != PKTUART_CONTROL_CHANNEL =>
+            {
+                Decoder::PktUart
+            }
+            _ => Decoder::Raw,
+        }
+    }
+
+    fn input_hint(self) -> &'static str {
+        match self {
+            Decoder::Raw => "",
+            Decoder::Serial | Decoder::SerialRequests => " <パス> <データ>",
+            Decoder::PktUart => " <アドレス> <ペイロード>",
+        }
+    }
+}
+
+struct Entry {
+    time: DateTime<Local>,
+    from: NodeID,
+    /// Sent from this dashboard rather than received.
+    sent: bool,
+    data: Vec<u8>,
+    /// Formatted lines without the time and sender, cached per display mode.
+    text_lines: Option<Vec<String>>,
+    hex_lines: Option<Vec<String>>,
+}
+
+impl Entry {
+    fn new(from: NodeID, sent: bool, data: Vec<u8>) -> Self {
+        Entry {
+            time: Local::now(),
+            from,
+            sent,
+            data,
+            text_lines: None,
+            hex_lines: None,
+        }
+    }
+
+    /// Lines of the entry; in hex mode only the first one is preceded by the time
+    /// and sender.
+    fn lines(&mut self, decoder: Decoder, hex: bool) -> &[String] {
+        let (data, sent) = (&self.data, self.sent);
+        if hex {
+            return self.hex_lines.get_or_insert_with(|| {
+                let mut lines = vec![format!("{} バイト", data.len())];
+                lines.extend(hexdump(data, 0).lines().map(|row| format!("  {row}")));
+                lines
+            });
+        }
+
+        self.text_lines.get_or_insert_with(|| {
+            let text = match decoder {
+                Decoder::Raw => escape_binary(data),
+                Decoder::Serial if !sent => describe_serial(data),
+                // requests written from the input line are kept in the view they were typed in
+                Decoder::Serial | Decoder::SerialRequests => describe_serial_request(data),
+                Decoder::PktUart => describe_packet(data),
+            };
+            let text = text.strip_suffix('\n').unwrap_or(&text);
+            text.split('\n').map(str::to_string).collect()
+        })
+    }
+
+    fn style(&self) -> Style {
+        if self.sent {
+            Style::new().fg(Color::Yellow)
+        } else {
+            Style::new()
+        }
+    }
+}
+
+/// Channels and nodes found by the refresher.
+struct Snapshot {
+    /// Channels that are subscribed, including the ones in `subscriptions`.
+    channels: BTreeMap<ChannelID, ChannelInfo>,
+    /// Subscriptions to the channels that are new since the previous snapshot.
+    subscriptions: Vec<(ChannelID, Subscription)>,
+    /// Declared metadata of the nodes, `None` if they have not declared themselves.
+    nodes: BTreeMap<NodeID, Option<NodeMeta>>,
+}
+
+struct ChannelView {
+    info: ChannelInfo,
+    decoder: Decoder,
+    entries: VecDeque<Entry>,
+    /// Entries received while another channel was selected.
+    unread: usize,
+    /// Forwards the channel's subscription to the dashboard.
+    forwarder: JoinHandle<()>,
+}
+
+impl Drop for ChannelView {
+    fn drop(&mut self) {
+        self.forwarder.abort();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Channels,
+    Input,
+    Search,
+}
+
+struct App<'a> {
+    client: &'a DCClient,
+    node_id: NodeID,
+    channels: Vec<ChannelView>,
+    selected: ListState,
+    /// Declared metadata of the nodes, `None` if they have not declared themselves.
+    nodes: BTreeMap<NodeID, Option<NodeMeta>>,
+    messages: mpsc::UnboundedSender<(ChannelID, Message)>,
+    /// Tells the refresher about nodes that have sent a message.
+    seen: mpsc::UnboundedSender<NodeID>,
+    focus: Focus,
+    hex: bool,
+    /// Lines scrolled up from the bottom of the scrollback; 0 follows new data.
+    scroll: usize,
+    /// Height of the scrollback at the last draw.
+    view_height: usize,
+    input: String,
+    /// Send the input line as escaped binary rather than text.
+    binary: bool,
+    /// Search pattern being typed.
+    search: String,
+    pattern: Option<String>,
+    status: String,
+}
+
+impl App<'_> {
+    fn current(&self) -> Option<&ChannelView> {
+        self.selected.selected().and_then(|i| self.channels.get(i))
+    }
+
+    /// Applies a snapshot taken by the refresher.
+    fn apply(&mut self, snapshot: Snapshot) {
+        let selected_id = self.current().map(|c| c.info.channel);
+
+        self.channels.retain(|view| {
+            snapshot
+                .channels
+                .get(&view.info.channel)
+                .is_some_and(|info| same_channel(&view.info, info))
+        });
+        for (id, mut subscription) in snapshot.subscriptions {
+            let Some(info) = snapshot.channels.get(&id) else {
+                continue;
+            };
+            let messages = self.messages.clone();
+            let forwarder = tokio::spawn(async move {
+                while let Some(message) = subscription.recv().await {
+                    if messages.send((id, message)).is_err() {
+                        break;
+                    }
+                }
+            });
+            self.channels.push(ChannelView {
+                decoder: Decoder::of(info),
+                info: info.clone(),
+                entries: VecDeque::new(),
+                unread: 0,
+                forwarder,
+            });
+        }
+        self.channels.sort_by_key(|view| view.info.channel);
+
+        let selected = selected_id
+            .and_then(|id| self.channels.iter().position(|c| c.info.channel == id))
+            .or((!self.channels.is_empty()).then_some(0));
+        self.selected.select(selected);
+
+        self.nodes = snapshot.nodes;
+    }
+
+    fn push(&mut self, channel: ChannelID, entry: Entry) {
+        let selected = self.current().map(|c| c.info.channel);
+        let Some(view) = self.channels.iter_mut().find(|c| c.info.channel == channel) else {
+            return;
+        };
+        if let btree_map::Entry::Vacant(node) = self.nodes.entry(entry.from) {
+            node.insert(None);
+            let _ = self.seen.send(entry.from);
+        }
+        if view.entries.len() == SCROLLBACK {
+            view.entries.pop_front();
+        }
+        view.entries.push_back(entry);
+        if selected != Some(channel) {
+            view.unread += 1;
+        }
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected.select(Some(index));
+        self.scroll = 0;
+        if let Some(view) = self.channels.get_mut(index) {
+            view.unread = 0;
+        }
+    }
+
+    /// Sends the input line to the selected channel.
+    async fn send_input(&mut self) {
+        let Some(view) = self.current() else {
+            return;
+        };
+        let (channel, decoder) = (view.info.channel, view.decoder);
+        let input = std::mem::take(&mut self.input);
+
+        let result = match decoder {
+            Decoder::Raw if self.binary => {
+                let data = intercept_escape_sequences(&input);
+                self.client
+                    .send_bin(channel, data.clone())
+                    .await
+                    .map(|_| data)
+                    .map_err(|e| e.to_string())
+            }
+            Decoder::Raw => self
+                .client
+                .send(channel, input.clone())
+                .await
+                .map(|_| input.into_bytes())
+                .map_err(|e| e.to_string()),
+            Decoder::Serial | Decoder::SerialRequests => {
+                let target = self
+                    .channels
+                    .iter()
+                    .find(|c| c.decoder == Decoder::SerialRequests)
+                    .map(|c| c.info.channel);
+                match (target, input.split_once(' ')) {
+                    (None, _) => Err(format!("チャンネル {REQUEST_CHANNEL_NAME} がありません")),
+                    (Some(target), Some((path, data))) => {
+                        let request = SerialRequest::Data {
+                            path: path.to_string(),
+                            data: intercept_escape_sequences(data),
+                        };
+                        match serde_json::to_vec(&request) {
+                            Ok(json) => self
+                                .client
+                                .send_typed(target, &request)
+                                .await
+                                .map(|_| json)
+                                .map_err(|e| e.to_string()),
+                            Err(e) => Err(e.to_string()),
+                        }
+                    }
+                    (Some(_), None) => Err("<パス> <データ> の形式で入力してください".to_string()),
+                }
+            }
+            Decoder::PktUart => match input.split_once(' ') {
+                Some((addr, payload)) => match parse_address(addr) {
+                    Ok(addr) => {
+                        let mut packet = vec![addr];
+                        packet.extend(intercept_escape_sequences(payload));
+                        self.client
+                            .send_bin(channel, packet.clone())
+                            .await
+                            .map(|_| packet)
+                            .map_err(|e| e.to_string())
+                    }
+                    Err(e) => Err(e),
+                },
+                None => Err("<アドレス> <ペイロード> の形式で入力してください".to_string()),
+            },
+        };
+
+        match result {
+            Ok(data) => {
+                let entry = Entry::new(self.node_id, true, data);
+                self.push(channel, entry);
+                self.status.clear();
+            }
+            Err(e) => self.status = format!("送信に失敗しました: {e}"),
+        }
+    }
+
+    /// Scrolls to the next (or previous) line matching the search pattern.
+    fn find(&mut self, forward: bool) {
+        let Some(pattern) = self.pattern.clone() else {
+            return;
+        };
+        let lines = self.render_lines();
+        let total = lines.len();
+        // line at the top of the view
+        let top = total.saturating_sub(self.scroll + self.view_height);
+        let matches = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, (text, _))| text.contains(&pattern))
+            .map(|(i, _)| i);
+
+        let found = if forward {
+            matches.into_iter().find(|&i| i > top)
+        } else {
+            matches.into_iter().rev().find(|&i| i < top)
+        };
+        match found {
+            Some(line) => {
+                self.scroll = total.saturating_sub(line + self.view_height);
+                self.status.clear();
+            }
+            None => self.status = format!("'{pattern}' は見つかりません"),
+        }
+    }
+
+    /// Lines of the selected channel's scrollback, with the style of each.
+    fn render_lines(&mut self) -> Vec<(String, Style)> {
+        let hex = self.hex;
+        let Some(view) = self
+            .selected
+            .selected()
+            .and_then(|i| self.channels.get_mut(i))
+        else {
+            return Vec::new();
+        };
+        let decoder = view.decoder;
+        view.entries
+            .iter_mut()
+            .flat_map(|entry| format_entry(&self.nodes, entry, decoder, hex))
+            .collect()
+    }
+
+    /// The `height` lines of the selected channel's scrollback that end `scroll`
+    /// lines above the bottom, keeping `scroll` within the scrollback. Only the
+    /// entries in the window are formatted.
+    fn visible_lines(&mut self, height: usize) -> Vec<(String, Style)> {
+        let hex = self.hex;
+        let Some(view) = self
+            .selected
+            .selected()
+            .and_then(|i| self.channels.get_mut(i))
+        else {
+            return Vec::new();
+        };
+        let decoder = view.decoder;
+
+        // walk up from the bottom to the entry at the top of the window
+        let mut below = 0;
+        let mut first = view.entries.len();
+        while first > 0 && below < self.scroll + height {
+            first -= 1;
+            below += view.entries[first].lines(decoder, hex).len();
+        }
+        if below < self.scroll + height {
+            self.scroll = self.scroll.min(below.saturating_sub(height));
+        }
+        // window in lines counted from `first`
+        let end = below - self.scroll;
+        let start = end.saturating_sub(height);
+
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        for entry in view.entries.range_mut(first..) {
+            if offset >= end {
+                break;
+            }
+            let formatted = format_entry(&self.nodes, entry, decoder, hex);
+            for (i, line) in formatted.into_iter().enumerate() {
+                if (start..end).contains(&(offset + i)) {
+                    lines.push(line);
+                }
+            }
+            offset += entry.lines(decoder, hex).len();
+        }
+        lines
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, input, status] = Layout::vertical([
+            Constraint::Min(0),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [side, scrollback] =
+            Layout::horizontal([Constraint::Length(36), Constraint::Min(0)]).areas(main);
+        let [channels, nodes] =
+            Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(side);
+
+        self.draw_channels(frame, channels);
+        self.draw_nodes(frame, nodes);
+        self.draw_scrollback(frame, scrollback);
+        self.draw_input(frame, input);
+
+        let help = match self.focus {
+            Focus::Channels => {
+                "q:終了 ↑↓:チャンネル PgUp/PgDn:スクロール x:16進/テキスト /:検索 n/N:次/前 i:入力"
+            }
+            Focus::Input => "Enter:送信 Tab:テキスト/バイナリ Esc:戻る",
+            Focus::Search => "Enter:検索 Esc:キャンセル",
+        };
+        let status_line = if self.status.is_empty() {
+            Line::from(help.dark_gray())
+        } else {
+            Line::from(self.status.as_str().red())
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+    }
+
+    fn draw_channels(&mut self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .channels
+            .iter()
+            .map(|view| {
+                let mut spans = vec![Span::raw(format!(
+                    "{:>3} {}",
+                    view.info.channel, view.info.name
+                ))];
+                if view.unread > 0 {
+                    spans.push(format!(" ({})", view.unread).cyan());
+                }
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(" チャンネル "))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.selected);
+    }
+
+    fn draw_nodes(&self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .nodes
+            .iter()
+            .map(|(id, meta)| {
+                let text = match meta {
+                    Some(meta) => format!("{id:>3} {meta} [{}]", meta.kind),
+                    None => format!("{id:>3}"),
+                };
+                if *id == self.node_id {
+                    ListItem::new(text.dark_gray())
+                } else {
+                    ListItem::new(text)
+                }
+            })
+            .collect();
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title(" ノード ")),
+            area,
+        );
+    }
+
+    fn draw_scrollback(&mut self, frame: &mut Frame, area: Rect) {
+        let title = match self.current() {
+            Some(view) => {
+                let mode = if self.hex { "16進" } else { "テキスト" };
+                let mut title = format!(" {} ({}) [{mode}]", view.info.name, view.info.channel);
+                if let Some(pattern) = &self.pattern {
+                    title.push_str(&format!(" 検索: {pattern}"));
+                }
+                if self.scroll > 0 {
+                    title.push_str(&format!(" ↑{}", self.scroll));
+                }
+                title + " "
+            }
+            None => " チャンネルがありません ".to_string(),
+        };
+
+        self.view_height = area.height.saturating_sub(2) as usize;
+        let lines = self.visible_lines(self.view_height);
+
+        let visible: Vec<Line> = lines
+            .into_iter()
+            .map(|(text, style)| {
+                let matched = self
+                    .pattern
+                    .as_ref()
+                    .is_some_and(|pattern| text.contains(pattern.as_str()));
+                let style = if matched {
+                    style.bg(Color::DarkGray)
+                } else {
+                    style
+                };
+                Line::styled(text, style)
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(visible).block(Block::bordered().title(title)),
+            area,
+        );
+    }
+
+    fn draw_input(&self, frame: &mut Frame, area: Rect) {
+        let (title, text) = match self.focus {
+            Focus::Search => (" 検索 ".to_string(), self.search.as_str()),
+            _ => {
+                let decoder = self.current().map(|c| c.decoder).unwrap_or(Decoder::Raw);
+                let mode = match decoder {
+                    Decoder::Raw if self.binary => "バイナリ",
+                    Decoder::Raw => "テキスト",
+                    Decoder::Serial | Decoder::SerialRequests => "シリアル",
+                    Decoder::PktUart => "PktUART",
+                };
+                (
+                    format!(" 入力 [{mode}]{} ", decoder.input_hint()),
+                    self.input.as_str(),
+                )
+            }
+        };
+
+        let style = if self.focus == Focus::Channels {
+            Style::new().dark_gray()
+        } else {
+            Style::new()
+        };
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title(title).border_style(style)),
+            area,
+        );
+        if self.focus != Focus::Channels {
+            let x = area.x + 1 + text.chars().count() as u16;
+            frame.set_cursor_position((x.min(area.right().saturating_sub(2)), area.y + 1));
+        }
+    }
+
+    /// Handles a key press, returning `false` to quit.
+    async fn key(&mut self, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return false;
+        }
+
+        match self.focus {
+            Focus::Channels => match key.code {
+                KeyCode::Char('q') => return false,
+                KeyCode::Up | KeyCode::Char('k') => {
+                    let i = self.selected.selected().unwrap_or(0);
+                    self.select(i.saturating_sub(1));
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    let i = self.selected.selected().map_or(0, |i| i + 1);
+                    if i < self.channels.len() {
+                        self.select(i);
+                    }
+                }
+                KeyCode::PageUp => self.scroll += self.view_height.max(1),
+                KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(self.view_height),
+                KeyCode::End => self.scroll = 0,
+                KeyCode::Char('x') => {
+                    self.hex = !self.hex;
+                    self.scroll = 0;
+                }
+                KeyCode::Char('/') => {
+                    self.search.clear();
+                    self.focus = Focus::Search;
+                }
+                KeyCode::Char('n') => self.find(true),
+                KeyCode::Char('N') => self.find(false),
+                KeyCode::Char('i') | KeyCode::Enter => {
+                    self.status.clear();
+                    self.focus = Focus::Input;
+                }
+                _ => {}
+            },
+            Focus::Input => match key.code {
+                KeyCode::Esc => self.focus = Focus::Channels,
+                KeyCode::Enter => self.send_input().await,
+                KeyCode::Tab => self.binary = !self.binary,
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                KeyCode::Char(c) => self.input.push(c),
+                _ => {}
+            },
+            Focus::Search => match key.code {
+                KeyCode::Esc => self.focus = Focus::Channels,
+                KeyCode::Enter => {
+                    self.pattern = if self.search.is_empty() {
+                        None
+                    } else {
+                        Some(self.search.clone())
+                    };
+                    self.focus = Focus::Channels;
+                    self.scroll = 0;
+                    self.find(false);
+                }
+                KeyCode::Backspace => {
+                    self.search.pop();
+                }
+                KeyCode::Char(c) => self.search.push(c),
+                _ => {}
+            },
+        }
+        true
+    }
+}
+
+/// Lines of `entry` with its time and sender, and their style.
+fn format_entry(
+    nodes: &BTreeMap<NodeID, Option<NodeMeta>>,
+    entry: &mut Entry,
+    decoder: Decoder,
+    hex: bool,
+) -> Vec<(String, Style)> {
+    let sender = match nodes.get(&entry.from) {
+        Some(Some(meta)) => format!("{}({})", meta.name, entry.from),
+        _ => entry.from.to_string(),
+    };
+    let mark = if entry.sent { "<" } else { ">" };
+    let prefix = format!("{} {sender}{mark} ", entry.time.format("%H:%M:%S%.3f"));
+    let style = entry.style();
+
+    entry
+        .lines(decoder, hex)
+        .iter()
+        .enumerate()
+        .map(|(i, line)| match i {
+            // the rows of a hex dump follow their header
+            0 => (format!("{prefix}{line}"), style),
+            _ if hex => (line.clone(), style),
+            _ => (format!("{prefix}{line}"), style),
+        })
+        .collect()
+}
+
+fn describe_serial(data: &[u8]) -> String {
+    match serde_json::from_slice::<SerialEvent>(data) {
+        Ok(SerialEvent::Opened { path }) => format!("{path}: 接続されました"),
+        Ok(SerialEvent::Closed { path }) => format!("{path}: 切断されました"),
+        Ok(SerialEvent::Line { path, line }) => {
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+            format!("{path}| {}", escape_binary(line))
+        }
+        Ok(SerialEvent::Ports { ports }) => {
+            let paths: Vec<&str> = ports.iter().map(|port| port.path.as_str()).collect();
+            format!("監視中のポート: {}", paths.join(", "))
+        }
+        Err(_) => escape_binary(data),
+    }
+}
+
+fn describe_serial_request(data: &[u8]) -> String {
+    match serde_json::from_slice::<SerialRequest>(data) {
+        Ok(SerialRequest::Data { path, data }) => {
+            format!("{path} への書き込み: {}", escape_binary(&data))
+        }
+        Ok(SerialRequest::OpenVPort { path, channel_name }) => {
+            format!("{path} の仮想ポートを開く: {channel_name}")
+        }
+        Ok(SerialRequest::List) => "ポート一覧の要求".to_string(),
+        Err(_) => escape_binary(data),
+    }
+}
+
+fn describe_packet(data: &[u8]) -> String {
+    let Some((addr, payload)) = data.split_first() else {
+        return "(空のパケット)".to_string();
+    };
+    let hex: Vec<String> = payload.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "[0x{addr:02x}] {}  |{}|",
+        hex.join(" "),
+        escape_binary(payload).replace('\n', r"\n")
+    )
+}
+
+/// Reads terminal events on a plain thread, like `attach` does for stdin.
+fn spawn_event_reader() -> mpsc::Receiver<io::Result<Event>> {
+    let (tx, rx) = mpsc::channel(16);
+    std::thread::spawn(move || {
+        loop {
+            let read = event::read();
+            let failed = read.is_err();
+            if tx.blocking_send(read).is_err() || failed {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Takes a snapshot of the channels and nodes, subscribing to the channels that
+/// are not in `subscribed`.
+async fn take_snapshot(
+    client: &DCClient,
+    node_id: NodeID,
+    subscribed: &BTreeMap<ChannelID, ChannelInfo>,
+    known: &BTreeSet<NodeID>,
+) -> Result<Snapshot, CliError> {
+    let mut channels = BTreeMap::new();
+    let mut subscriptions = Vec::new();
+    for (id, info) in snapshot(client).await? {
+        let is_subscribed = subscribed
+            .get(&id)
+            .is_some_and(|before| same_channel(before, &info));
+        if !is_subscribed {
+            let Ok(subscription) = client.subscribe(id).await else {
+                continue;
+            };
+            subscriptions.push((id, subscription));
+        }
+        channels.insert(id, info);
+    }
+
+    // nodes that are gone are dropped unless they still supply a channel
+    let suppliers: BTreeSet<NodeID> = channels.values().map(|info| info.supplied_by).collect();
+    let mut nodes = BTreeMap::new();
+    for &id in known.union(&suppliers) {
+        let meta = match client.node_info(id).await {
+            Ok(info) => info.meta,
+            Err(_) => None,
+        };
+        if meta.is_some() || suppliers.contains(&id) || id == node_id {
+            nodes.insert(id, meta);
+        }
+    }
+
+    Ok(Snapshot {
+        channels,
+        subscriptions,
+        nodes,
+    })
+}
+
+/// Follows channels being opened and closed and the nodes' metadata on a task of
+/// its own, so that a slow server does not freeze the dashboard. `seen` tells it
+/// about the nodes that have sent a message.
+fn spawn_refresher(
+    client: DCClient,
+    node_id: NodeID,
+    mut seen: mpsc::UnboundedReceiver<NodeID>,
+) -> (mpsc::Receiver<Result<Snapshot, String>>, JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel(1);
+    let task = tokio::spawn(async move {
+        let mut subscribed = BTreeMap::new();
+        let mut known = BTreeSet::from([node_id]);
+        loop {
+            while let Ok(id) = seen.try_recv() {
+                known.insert(id);
+            }
+
+            if client.is_connected() {
+                let refresh = take_snapshot(&client, node_id, &subscribed, &known);
+                let result = match timeout(REFRESH_TIMEOUT, refresh).await {
+                    Ok(Ok(snapshot)) => {
+                        subscribed = snapshot.channels.clone();
+                        known = snapshot.nodes.keys().copied().collect();
+                        Ok(snapshot)
+                    }
+                    Ok(Err(e)) => Err(e.to_string()),
+                    Err(_) => Err("サーバーからの応答がないため一覧を更新できません".to_string()),
+                };
+                if tx.send(result).await.is_err() {
+                    break;
+                }
+            }
+
+            tokio::time::sleep(REFRESH_INTERVAL).await;
+        }
+    });
+    (rx, task)
+}
+
+async fn run(
+    app: &mut App<'_>,
+    terminal: &mut DefaultTerminal,
+    mut messages: mpsc::UnboundedReceiver<(ChannelID, Message)>,
+    mut snapshots: mpsc::Receiver<Result<Snapshot, String>>,
+) -> Result<(), CliError> {
+    let mut events = spawn_event_reader();
+    let mut state = app.client.state();
+
+    loop {
+        terminal
+            .draw(|frame| app.draw(frame))
+            .map_err(CliError::Io)?;
+
+        select! {
+            event = events.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                if let Event::Key(key) = event.map_err(CliError::Io)?
+                    && key.kind == KeyEventKind::Press
+                    && !app.key(key).await
+                {
+                    break;
+                }
+            }
+            Some((channel, message)) = messages.recv() => {
+                let entry = Entry::new(message.from(), false, message.as_bytes().to_vec());
+                app.push(channel, entry);
+            }
+            Some(snapshot) = snapshots.recv() => match snapshot {
+                Ok(snapshot) => app.apply(snapshot),
+                Err(e) => app.status = e,
+            },
+            Ok(()) = state.changed() => {
+                if !app.client.is_connected() {
+                    app.status = "サーバーとの接続が切断されました".to_string();
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn handle_tui(client: &DCClient) -> Result<(), CliError> {
+    let node_id = client.get_node_id().await.unwrap_or_default();
+    let (messages, received) = mpsc::unbounded_channel();
+    let (seen, seen_rx) = mpsc::unbounded_channel();
+    let (snapshots, refresher) = spawn_refresher(client.clone(), node_id, seen_rx);
+    let mut app = App {
+        client,
+        node_id,
+        channels: Vec::new(),
+        selected: ListState::default(),
+        nodes: BTreeMap::new(),
+        messages,
+        seen,
+        focus: Focus::Channels,
+        hex: false,
+        scroll: 0,
+        view_height: 0,
+        input: String::new(),
+        binary: false,
+        search: String::new(),
+        pattern: None,
+        status: String::new(),
+    };
+
+    let mut terminal = ratatui::init();
+    let result = run(&mut app, &mut terminal, received, snapshots).await;
+    ratatui::restore();
+    refresher.abort();
+
+    result
+}
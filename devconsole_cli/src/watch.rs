@@ -0,0 +1,127 @@
+//! `watch`: report channels as they are opened and closed.
+
+use std::collections::BTreeMap;
+
+use chrono::{Local, SecondsFormat};
+use devconsole::{ChannelEvent, ChannelID, ChannelInfo, DCClient, DCClientError};
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use crate::CliError;
+
+#[derive(Debug, Clone, Copy)]
+enum Change {
+    /// Already open when watching started.
+    Present,
+    Opened,
+    Closed,
+}
+
+impl Change {
+    fn name(self) -> &'static str {
+        match self {
+            Change::Present => "present",
+            Change::Opened => "opened",
+            Change::Closed => "closed",
+        }
+    }
+
+    fn mark(self) -> char {
+        match self {
+            Change::Present => '=',
+            Change::Opened => '+',
+            Change::Closed => '-',
+        }
+    }
+}
+
+/// Fetches the info of every open channel. Channels closed between the list and
+/// the info request are left out.
+pub(crate) async fn snapshot(
+    client: &DCClient,
+) -> Result<BTreeMap<ChannelID, ChannelInfo>, CliError> {
+    let ids = client
+        .channel_list()
+        .await
+        .map_err(CliError::client("チャンネル一覧の取得"))?;
+
+    let mut channels = BTreeMap::new();
+    for id in ids {
+        if let Ok(info) = client.channel_info(id).await {
+            channels.insert(id, info);
+        }
+    }
+
+    Ok(channels)
+}
+
+fn report(event: Change, info: &ChannelInfo, json: bool) {
+    let time = Local::now();
+    if json {
+        let record = json!({
+            "time": time.to_rfc3339_opts(SecondsFormat::Millis, false),
+            "event": event.name(),
+            "channel": info,
+        });
+        println!("{record}");
+    } else {
+        println!(
+            "{} {} {} ({}) 提供者: {}",
+            time.format("%H:%M:%S%.3f"),
+            event.mark(),
+            info.name,
+            info.channel,
+            info.supplier_info()
+        );
+    }
+}
+
+/// Whether `before` and `after` describe the same channel rather than a new
+/// channel that got the ID of a closed one. The supplier is not compared, since
+/// a persistent channel keeps its ID when it is re-bound to a new supplier.
+pub(crate) fn same_channel(before: &ChannelInfo, after: &ChannelInfo) -> bool {
+    before.name == after.name
+}
+
+pub async fn handle_watch(client: &DCClient, json: bool) -> Result<(), CliError> {
+    // start watching before taking the snapshot, so that no channel is missed
+    let (tx, mut events) = mpsc::unbounded_channel();
+    client
+        .watch_channels(tx)
+        .await
+        .map_err(CliError::client("チャンネルの開閉の監視"))?;
+
+    if !json {
+        println!("チャンネルの開閉を監視しています。Ctrl+C で終了します。");
+    }
+
+    let mut known = snapshot(client).await?;
+    for info in known.values() {
+        report(Change::Present, info, json);
+    }
+
+    while let Some(event) = events.recv().await {
+        match event {
+            ChannelEvent::Opened(info) => {
+                // also in the snapshot if opened while it was taken
+                if known
+                    .get(&info.channel)
+                    .is_some_and(|known| same_channel(known, &info))
+                {
+                    continue;
+                }
+                report(Change::Opened, &info, json);
+                known.insert(info.channel, info);
+            }
+            ChannelEvent::Closed(info) => {
+                known.remove(&info.channel);
+                report(Change::Closed, &info, json);
+            }
+        }
+    }
+
+    Err(CliError::Client {
+        action: "チャンネルの開閉の監視",
+        source: DCClientError::ConnectionBroken,
+    })
+}
@@ -28,9 +28,12 @@ pub async fn main() {
         .format_module_path(false)
         .format_target(false)
         .init();
-    let mut client = devconsole::DCClient::new("ws://127.0.0.1:9001")
-        .await
-        .unwrap();
+    let client = devconsole::DCClient::new_with_meta(
+        "ws://127.0.0.1:9001",
+        devconsole::NodeMeta::new("data_logger", "logger"),
+    )
+    .await
+    .unwrap();
 
     let mut listening_channels = vec![];
 
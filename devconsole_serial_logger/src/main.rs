@@ -5,12 +5,12 @@ mod pkt_uart;
 
 use std::collections::HashMap;
 
-use devconsole::DCClient;
-use devconsole_serial_protocol::SerialEvent;
-use log::debug;
+use devconsole::{DCClient, NodeMeta};
+use devconsole_serial_protocol::{CHANNEL_NAME, SerialEvent};
+use log::{debug, warn};
 use tokio::{spawn, sync::mpsc};
 
-async fn get_serial_monitor_cid(client: &mut DCClient) -> Option<u64> {
+async fn get_serial_monitor_cid(client: &DCClient) -> Option<u64> {
     let channel_list = client
         .channel_list()
         .await
@@ -21,7 +21,7 @@ async fn get_serial_monitor_cid(client: &mut DCClient) -> Option<u64> {
             .channel_info(cid)
             .await
             .expect("Failed to get channel info");
-        if info.name == "SerialMonitor" {
+        if info.name == CHANNEL_NAME {
             return Some(cid);
         }
     }
@@ -105,25 +105,35 @@ pub async fn main() {
         .format_target(false)
         .init();
 
-    let mut client = DCClient::new("ws://127.0.0.1:9001").await.unwrap();
+    let client = DCClient::new_with_meta(
+        "ws://127.0.0.1:9001",
+        NodeMeta::new("serial_logger", "logger"),
+    )
+    .await
+    .unwrap();
 
-    let (tx, mut rx) = mpsc::channel(100);
+    while get_serial_monitor_cid(&client).await.is_none() {}
 
-    while get_serial_monitor_cid(&mut client).await.is_none() {}
-
-    let serial_monitor_cid = get_serial_monitor_cid(&mut client)
+    let serial_monitor_cid = get_serial_monitor_cid(&client)
         .await
         .expect("Failed to get SerialMonitor channel ID");
 
-    client
-        .listen(serial_monitor_cid, Some(tx), None)
+    let mut events = client
+        .subscribe_typed::<SerialEvent>(serial_monitor_cid)
         .await
         .expect("Failed to listen to SerialMonitor channel");
 
     let mut handler = Handler::new();
 
-    while let Some((_nid, msg)) = rx.recv().await {
-        match serde_json::from_str(&msg).unwrap() {
+    while let Some(event) = events.recv().await {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Ignoring malformed serial event: {e}");
+                continue;
+            }
+        };
+        match event {
             SerialEvent::Opened { path } => {
                 handler.add_device(path).await;
             }
@@ -139,6 +149,7 @@ pub async fn main() {
             SerialEvent::Closed { path } => {
                 handler.remove_device(&path);
             }
+            SerialEvent::Ports { .. } => {}
         }
     }
 }
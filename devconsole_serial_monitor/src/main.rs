@@ -5,9 +5,11 @@ extern crate env_logger as logger;
 mod device_watcher;
 mod serial_monitor;
 
-use devconsole::{ChannelID, DCClient};
-use devconsole_serial_protocol::{SerialEvent, SerialRequest};
-use std::collections::HashMap;
+use devconsole::{ChannelID, DCClient, NodeMeta, Subscription, TypedSubscription};
+use devconsole_serial_protocol::{
+    CHANNEL_NAME, REQUEST_CHANNEL_NAME, SerialEvent, SerialPort, SerialRequest,
+};
+use std::collections::{BTreeSet, HashMap};
 use tokio::{
     select, spawn,
     sync::mpsc::{self, Receiver, Sender},
@@ -79,6 +81,9 @@ impl RequestMuxer {
                         Some(SerialRequest::OpenVPort { path: _, channel_name: _ }) => {
                             error!("OpenVPort request received in muxer, which is unexpected");
                         },
+                        Some(SerialRequest::List) => {
+                            error!("List request received in muxer, which is unexpected");
+                        },
 
                         None => {
                             error!("Error receiving data request");
@@ -107,7 +112,7 @@ impl RequestMuxer {
 }
 
 async fn monitor(
-    client: &mut DCClient,
+    client: &DCClient,
     channel: ChannelID,
     mut data_rx: Receiver<SerialRequest>,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -118,6 +123,7 @@ async fn monitor(
 
     let mut req_muxer = RequestMuxer::new();
     let mut vports: HashMap<String, u64> = HashMap::new();
+    let mut ports: BTreeSet<String> = BTreeSet::new();
 
     loop {
         select! {
@@ -150,11 +156,23 @@ async fn monitor(
                     }
                     Some(SerialRequest::OpenVPort { path, channel_name }) => {
                         if !vports.contains_key(&path) {
-                            let cid = client.open(channel_name).await?;
+                            let cid = client.open_persistent(channel_name).await?;
                             client.listen(cid, None, Some(data_inner_tx.clone())).await?;
                             vports.insert(path.clone(), cid);
                         }
                     }
+                    Some(SerialRequest::List) => {
+                        let evt = SerialEvent::Ports {
+                            ports: ports
+                                .iter()
+                                .map(|path| SerialPort {
+                                    path: path.clone(),
+                                    vport: vports.get(path).copied(),
+                                })
+                                .collect(),
+                        };
+                        client.send_typed(channel, &evt).await?;
+                    }
                     None => {
                         error!("Error receiving message from data channel");
                         break;
@@ -165,11 +183,11 @@ async fn monitor(
             msg = dev_rx.recv() => {
                 match msg {
                     Some(device_watcher::Event::DeviceFound(device)) => {
+                        ports.insert(device.clone());
                         let evt = SerialEvent::Opened {
                             path: device.clone(),
                         };
-                        let payload = serde_json::to_string(&evt).unwrap();
-                        client.send(channel, payload).await.unwrap();
+                        client.send_typed(channel, &evt).await.unwrap();
 
                         let tx = serial_tx.clone();
 
@@ -194,17 +212,16 @@ async fn monitor(
                             path: path.clone(),
                             line: line.clone(),
                         };
-                        let payload = serde_json::to_string(&evt).unwrap();
-                        client.send(channel, payload).await.unwrap();
+                        client.send_typed(channel, &evt).await.unwrap();
 
                         if let Some(cid) = vports.get(&path) {
                             client.send_bin(*cid, line).await.unwrap();
                         }
                     }
                     Some(serial_monitor::Event::Closed(path)) => {
+                        ports.remove(&path);
                         let evt = SerialEvent::Closed { path };
-                        let payload = serde_json::to_string(&evt).unwrap();
-                        client.send(channel, payload).await.unwrap();
+                        client.send_typed(channel, &evt).await.unwrap();
                     }
                     None => {
                         error!("Error receiving message from serial device");
@@ -219,11 +236,17 @@ async fn monitor(
 }
 
 async fn outbound_transformer(
-    mut outbound_rx: Receiver<(u64, String)>,
+    mut requests: TypedSubscription<SerialRequest>,
     req_tx: Sender<SerialRequest>,
 ) -> () {
-    while let Some((_from, data)) = outbound_rx.recv().await {
-        let msg = serde_json::from_str::<SerialRequest>(&data).expect("Failed to parse request");
+    while let Some(request) = requests.recv().await {
+        let msg = match request {
+            Ok(msg) => msg,
+            Err(e) => {
+                warn!("Ignoring malformed request: {e}");
+                continue;
+            }
+        };
 
         req_tx
             .send(msg)
@@ -235,6 +258,25 @@ async fn outbound_transformer(
     }
 }
 
+/// Forwards requests sent to the event channel, where they went before
+/// `SerialMonitorRequest` existed, so that older senders keep working.
+async fn legacy_transformer(mut messages: Subscription, req_tx: Sender<SerialRequest>) -> () {
+    while let Some(message) = messages.recv().await {
+        // the events published by the monitor itself share this channel
+        let Ok(msg) = message.decode::<SerialRequest>() else {
+            continue;
+        };
+        debug!(
+            "Request from node {} on {CHANNEL_NAME}; send it to {REQUEST_CHANNEL_NAME} instead",
+            message.from()
+        );
+
+        if req_tx.send(msg).await.is_err() {
+            break;
+        }
+    }
+}
+
 #[tokio::main]
 pub async fn main() {
     // initialize the logger as debug level
@@ -242,23 +284,35 @@ pub async fn main() {
         .filter(None, log::LevelFilter::Debug)
         .init();
 
-    let mut client = DCClient::new("ws://localhost:9001")
-        .await
-        .expect("Failed to connect to WebSocket server");
+    let client = DCClient::new_with_meta(
+        "ws://localhost:9001",
+        NodeMeta::new("serial_monitor", "monitor"),
+    )
+    .await
+    .expect("Failed to connect to WebSocket server");
 
     let channel = client
-        .open("SerialMonitor".to_string())
+        .open_persistent_typed::<SerialEvent>(CHANNEL_NAME.to_string())
         .await
         .expect("Failed to open channel");
 
-    let (outbound_tx, outbound_rx) = mpsc::channel(64);
-    client
-        .listen(channel, Some(outbound_tx.clone()), None)
+    let request_channel = client
+        .open_persistent_typed::<SerialRequest>(REQUEST_CHANNEL_NAME.to_string())
+        .await
+        .expect("Failed to open request channel");
+
+    let requests = client
+        .subscribe_typed::<SerialRequest>(request_channel)
+        .await
+        .expect("Failed to listen");
+    let legacy_requests = client
+        .subscribe(channel)
         .await
         .expect("Failed to listen");
     let (req_tx, req_rx) = mpsc::channel(64);
-    spawn(outbound_transformer(outbound_rx, req_tx));
+    spawn(outbound_transformer(requests, req_tx.clone()));
+    spawn(legacy_transformer(legacy_requests, req_tx));
 
     info!("Starting serial_monitor...");
-    monitor(&mut client, channel, req_rx).await.unwrap();
+    monitor(&client, channel, req_rx).await.unwrap();
 }
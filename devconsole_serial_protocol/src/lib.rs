@@ -1,14 +1,58 @@
+pub mod pktuart;
+
+use devconsole::Schema;
 use serde::{Deserialize, Serialize};
 
+/// Name of the channel the serial monitor publishes its `SerialEvent`s on.
+pub const CHANNEL_NAME: &str = "SerialMonitor";
+
+/// Name of the channel the serial monitor takes `SerialRequest`s from. Requests
+/// sent to [`CHANNEL_NAME`], as before this channel existed, are still accepted.
+pub const REQUEST_CHANNEL_NAME: &str = "SerialMonitorRequest";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerialPort {
+    pub path: String,
+    /// Channel the port is mirrored to by `OpenVPort`, if any.
+    pub vport: Option<u64>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum SerialEvent {
-    Opened { path: String },
-    Line { path: String, line: Vec<u8> },
-    Closed { path: String },
+    Opened {
+        path: String,
+    },
+    Line {
+        path: String,
+        line: Vec<u8>,
+    },
+    Closed {
+        path: String,
+    },
+    /// Answer to `SerialRequest::List`.
+    Ports {
+        ports: Vec<SerialPort>,
+    },
+}
+
+impl Schema for SerialEvent {
+    const NAME: &'static str = "SerialEvent";
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum SerialRequest {
-    Data { path: String, data: Vec<u8> },
-    OpenVPort { path: String, channel_name: String },
+    Data {
+        path: String,
+        data: Vec<u8>,
+    },
+    OpenVPort {
+        path: String,
+        channel_name: String,
+    },
+    /// Asks for the ports being monitored, answered with `SerialEvent::Ports`.
+    List,
+}
+
+impl Schema for SerialRequest {
+    const NAME: &'static str = "SerialRequest";
 }
@@ -0,0 +1,21 @@
+//! Requests to `dc_pktuart`, which bridges PktUART frames on a channel to packets
+//! on another.
+
+use devconsole::{ChannelID, Schema};
+use serde::{Deserialize, Serialize};
+
+/// Name of the channel `dc_pktuart` takes `BridgeRequest`s from.
+pub const CONTROL_CHANNEL_NAME: &str = "PktUART";
+
+/// Asks `dc_pktuart` to decode the frames arriving on `src` into `[address, payload...]`
+/// messages on the channel named `dst_ch_name`, and to frame the messages sent there
+/// back to `src`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BridgeRequest {
+    pub src: ChannelID,
+    pub dst_ch_name: String,
+}
+
+impl Schema for BridgeRequest {
+    const NAME: &'static str = "PktUartRequest";
+}
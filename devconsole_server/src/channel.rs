@@ -1,18 +1,31 @@
-use devconsole::{ChannelID, NodeID};
+use devconsole::{ChannelID, ChannelInfo, NodeID, NodeMeta};
+use tokio::time::Instant;
 
 #[derive(Clone)]
 pub struct Channel {
     id: ChannelID,
     name: String,
     supplied_by: NodeID,
+    persistent: bool,
+    schema: Option<String>,
+    orphaned_since: Option<Instant>,
 }
 
 impl Channel {
-    pub fn new(id: ChannelID, name: String, supplied_by: NodeID) -> Self {
+    pub fn new(
+        id: ChannelID,
+        name: String,
+        supplied_by: NodeID,
+        persistent: bool,
+        schema: Option<String>,
+    ) -> Self {
         Channel {
             id,
             name,
             supplied_by,
+            persistent,
+            schema,
+            orphaned_since: None,
         }
     }
 
@@ -27,4 +40,35 @@ impl Channel {
     pub fn supplied_by(&self) -> NodeID {
         self.supplied_by
     }
+
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    pub fn orphaned_since(&self) -> Option<Instant> {
+        self.orphaned_since
+    }
+
+    /// Info of the channel as reported to clients.
+    pub fn info(&self, supplier: Option<NodeMeta>) -> ChannelInfo {
+        ChannelInfo {
+            channel: self.id,
+            name: self.name.clone(),
+            supplied_by: self.supplied_by,
+            supplier,
+            schema: self.schema.clone(),
+        }
+    }
+
+    /// Marks the channel as having lost its supplier.
+    pub fn orphan(&mut self) {
+        self.orphaned_since = Some(Instant::now());
+    }
+
+    /// Hands an orphaned channel over to a new supplier.
+    pub fn rebind(&mut self, supplied_by: NodeID, schema: Option<String>) {
+        self.supplied_by = supplied_by;
+        self.schema = schema;
+        self.orphaned_since = None;
+    }
 }
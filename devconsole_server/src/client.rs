@@ -1,4 +1,4 @@
-use devconsole::{ChannelID, Event, NodeID};
+use devconsole::{ChannelID, Event, NodeID, NodeMeta};
 use futures_util::SinkExt;
 use futures_util::lock::Mutex;
 use futures_util::stream::SplitSink;
@@ -6,13 +6,41 @@ use log::error;
 use std::cell::RefCell;
 use std::sync::Arc;
 use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 use tokio_tungstenite::WebSocketStream;
 use tokio_tungstenite::tungstenite::Message;
 
+/// Where events for a client are written to.
+pub enum ClientWriter {
+    WebSocket(SplitSink<WebSocketStream<TcpStream>, Message>),
+    /// In-memory link to a `DCClient` in the same process.
+    Loopback(mpsc::UnboundedSender<Event>),
+}
+
+impl ClientWriter {
+    async fn send(&mut self, event: Event) -> Result<(), String> {
+        match self {
+            ClientWriter::WebSocket(writer) => {
+                let msg = serde_json::to_string(&event).map_err(|e| e.to_string())?;
+                writer
+                    .send(Message::Text(msg.into()))
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+            ClientWriter::Loopback(tx) => tx
+                .send(event)
+                .map_err(|_| "Loopback client is gone".to_string()),
+        }
+    }
+}
+
 struct Client {
-    writer: SplitSink<WebSocketStream<TcpStream>, Message>,
+    writer: ClientWriter,
     node_id: NodeID,
+    meta: Option<NodeMeta>,
     listening_channels: RefCell<Vec<ChannelID>>,
+    /// Told about channels being opened and closed.
+    watching_channels: bool,
 }
 
 #[derive(Clone)]
@@ -25,11 +53,13 @@ impl PartialEq for SharedClient {
 }
 
 impl SharedClient {
-    pub fn new(writer: SplitSink<WebSocketStream<TcpStream>, Message>, node_id: NodeID) -> Self {
+    pub fn new(writer: ClientWriter, node_id: NodeID) -> Self {
         SharedClient(Arc::new(Mutex::new(Client {
             writer,
             node_id,
+            meta: None,
             listening_channels: RefCell::new(Vec::new()),
+            watching_channels: false,
         })))
     }
 
@@ -37,19 +67,20 @@ impl SharedClient {
         self.0.lock().await.node_id
     }
 
+    pub async fn meta(&self) -> Option<NodeMeta> {
+        self.0.lock().await.meta.clone()
+    }
+
+    pub async fn set_meta(&self, meta: NodeMeta) {
+        self.0.lock().await.meta = Some(meta);
+    }
+
     pub async fn send_event(&self, event: Event) -> Result<(), String> {
         // info!("Sending event: {:?}", event);
-        let msg = serde_json::to_string(&event).unwrap().into();
-        self.0
-            .lock()
-            .await
-            .writer
-            .send(Message::Text(msg))
-            .await
-            .map_err(|e| {
-                error!("Error sending event: {e}");
-                e.to_string()
-            })?;
+        self.0.lock().await.writer.send(event).await.map_err(|e| {
+            error!("Error sending event: {e}");
+            e
+        })?;
 
         Ok(())
     }
@@ -68,4 +99,20 @@ impl SharedClient {
             Err(format!("Already listening to channel {channel}"))
         }
     }
+
+    pub async fn watch_channels(&self) {
+        self.0.lock().await.watching_channels = true;
+    }
+
+    pub async fn is_watching_channels(&self) -> bool {
+        self.0.lock().await.watching_channels
+    }
+
+    pub async fn unlisten(&self, channel: ChannelID) {
+        let client = self.0.lock().await;
+        client
+            .listening_channels
+            .borrow_mut()
+            .retain(|&c| c != channel);
+    }
 }
@@ -0,0 +1,180 @@
+use devconsole::{Event, NodeInfo};
+use futures_util::{Stream, StreamExt, future::ready};
+use log::{error, info};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::accept_async;
+
+use crate::{
+    client::{ClientWriter, SharedClient},
+    server::{RPC_CALL_TIMEOUT, SharedServer},
+};
+
+/// Serves one WebSocket connection until it is closed.
+pub async fn client_handler(stream: TcpStream, server: SharedServer) {
+    let (writer, reader) = match accept_async(stream).await {
+        Ok(ws_stream) => ws_stream.split(),
+        Err(e) => {
+            error!("Error during WebSocket handshake: {e}");
+            return;
+        }
+    };
+
+    let events = reader
+        .take_while(|x| {
+            if let Err(e) = x {
+                error!("Error receiving message: {e}");
+            }
+            ready(x.is_ok())
+        })
+        .filter_map(|msg| {
+            let evt = match msg {
+                Ok(msg) if msg.is_binary() || msg.is_text() => msg
+                    .to_text()
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| serde_json::from_str::<Event>(text).map_err(|e| e.to_string()))
+                    .inspect_err(|e| error!("Ignoring malformed message: {e}"))
+                    .ok(),
+                _ => None,
+            };
+            ready(evt)
+        });
+
+    serve_client(ClientWriter::WebSocket(writer), events, server).await;
+}
+
+/// Serves one client, whatever the transport, until `events` ends.
+pub(crate) async fn serve_client(
+    writer: ClientWriter,
+    events: impl Stream<Item = Event>,
+    server: SharedServer,
+) {
+    let node_id = server.get_new_node_id().await;
+    let client = SharedClient::new(writer, node_id);
+
+    server.add_connection(client.clone()).await;
+
+    if client
+        .send_event(Event::NodeIDNotification { node_id })
+        .await
+        .is_err()
+    {
+        server.remove_connection(&client).await;
+        return;
+    }
+
+    let mut events = std::pin::pin!(events);
+
+    while let Some(evt) = events.next().await {
+        // debug!("Received message: {evt:?}");
+
+        match evt {
+            Event::Data { channel, data, .. } => {
+                server
+                    .broadcast_data(channel, data, client.node_id().await)
+                    .await;
+            }
+            Event::DataBin { channel, data, .. } => {
+                server
+                    .broadcast_bin_data(channel, data, client.node_id().await)
+                    .await;
+            }
+            Event::Direct {
+                to, channel, data, ..
+            } => {
+                server.unicast_data(channel, data, node_id, to).await;
+            }
+            Event::DirectBin {
+                to, channel, data, ..
+            } => {
+                server.unicast_bin_data(channel, data, node_id, to).await;
+            }
+            Event::NodeMetaNotification(meta) => {
+                info!(
+                    "Node {node_id} is {meta} (kind: {}, pid: {})",
+                    meta.kind, meta.pid
+                );
+                client.set_meta(meta).await;
+            }
+            Event::NodeInfoRequest(target) => {
+                let response = Event::NodeInfoResponse(NodeInfo {
+                    node_id: target,
+                    meta: server.get_node_meta(target).await,
+                });
+                let _ = client.send_event(response).await;
+            }
+            Event::ChannelOpenRequest {
+                name,
+                persistent,
+                schema,
+            } => {
+                let channel = server.new_channel(name, node_id, persistent, schema).await;
+                let _ = client
+                    .send_event(Event::ChannelOpenResponse {
+                        channel,
+                        success: true,
+                    })
+                    .await;
+            }
+            Event::RpcRequest {
+                channel,
+                call_id,
+                data,
+                timeout_ms,
+            } => {
+                let timeout = timeout_ms.map_or(RPC_CALL_TIMEOUT, Duration::from_millis);
+                server
+                    .route_rpc_request(node_id, channel, call_id, data, timeout)
+                    .await;
+            }
+            Event::RpcResponse { call_id, result } => {
+                server.route_rpc_response(node_id, call_id, result).await;
+            }
+            Event::ChannelListenRequest { channel } => {
+                let response = Event::ChannelListenResponse {
+                    channel,
+                    success: client.listen(channel).await.is_ok(),
+                };
+                let _ = client.send_event(response).await;
+            }
+            Event::ChannelUnlistenRequest { channel } => {
+                client.unlisten(channel).await;
+            }
+            Event::ChannelWatchRequest => {
+                client.watch_channels().await;
+                let response = Event::ChannelWatchResponse { success: true };
+                let _ = client.send_event(response).await;
+            }
+            Event::ChannelCloseRequest { channel } => {
+                info!("Received ChannelCloseRequest for channel {channel}");
+            }
+
+            Event::ChannelListRequest => {
+                let channels = server.get_channel_ids().await;
+                let response = Event::ChannelListResponse { channels };
+                let _ = client.send_event(response).await;
+            }
+
+            Event::ChannelInfoRequest(channel) => {
+                if let Some(info) = server.get_channel(channel).await {
+                    let supplier = server.get_node_meta(info.supplied_by()).await;
+                    let response = Event::ChannelInfoResponse(info.info(supplier));
+                    let _ = client.send_event(response).await;
+                } else {
+                    error!("ChannelInfoRequest for unknown channel {channel}");
+                    let response = Event::RequestFailed {
+                        request: Box::new(Event::ChannelInfoRequest(channel)),
+                        reason: format!("Unknown channel {channel}"),
+                    };
+                    let _ = client.send_event(response).await;
+                }
+            }
+
+            _ => {
+                error!("Unhandled event: {evt:?}");
+            }
+        }
+    }
+
+    server.remove_connection(&client).await;
+}
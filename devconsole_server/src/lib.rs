@@ -0,0 +1,153 @@
+mod channel;
+mod client;
+mod handler;
+mod id_manager;
+mod metrics;
+mod server;
+
+use std::{future::Future, io, net::SocketAddr, sync::Mutex, time::Duration};
+
+use devconsole::{DCClient, Event, NodeMeta};
+use futures_util::stream;
+use tokio::{
+    net::{TcpListener, ToSocketAddrs},
+    sync::mpsc,
+    task::{JoinHandle, JoinSet},
+};
+
+use crate::client::ClientWriter;
+
+pub use handler::client_handler;
+pub use metrics::serve as serve_metrics;
+pub use server::{PERSISTENT_CHANNEL_GRACE, RPC_CALL_TIMEOUT, SharedServer};
+
+/// A server running on the current tokio runtime, returned by [`spawn_on`].
+///
+/// Dropping the handle stops the server and closes every connection.
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    server: SharedServer,
+    acceptor: JoinHandle<()>,
+    reaper: JoinHandle<()>,
+    // in-memory connections made through the handle, closed along with it
+    local_connections: Mutex<JoinSet<()>>,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// URL to pass to `DCClient::new`.
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.local_addr)
+    }
+
+    pub fn server(&self) -> &SharedServer {
+        &self.server
+    }
+
+    /// Connects a client to this server without going through TCP. See [`connect_local`].
+    ///
+    /// Unlike the free function, the connection is owned by the handle and closed
+    /// when the handle is dropped.
+    pub async fn connect_local(&self, meta: NodeMeta) -> DCClient {
+        let (serve, to_server, from_server) = loopback(&self.server);
+        {
+            let mut connections = self.local_connections.lock().unwrap();
+            while connections.try_join_next().is_some() {}
+            connections.spawn(serve);
+        }
+
+        DCClient::new_loopback(meta, to_server, from_server).await
+    }
+
+    /// Waits until the server stops accepting connections.
+    pub async fn wait(&mut self) {
+        let _ = (&mut self.acceptor).await;
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.acceptor.abort();
+        self.reaper.abort();
+    }
+}
+
+/// Starts a server listening on `addr`. Use port 0 to get an ephemeral port,
+/// which can be read back with [`ServerHandle::local_addr`].
+pub async fn spawn_on(addr: impl ToSocketAddrs) -> io::Result<ServerHandle> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let server = SharedServer::new_default();
+
+    let acceptor = tokio::spawn(accept_loop(listener, server.clone()));
+
+    let reaper = server.clone();
+    let reaper = tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            reaper
+                .expire_orphaned_channels(PERSISTENT_CHANNEL_GRACE)
+                .await;
+            reaper.expire_pending_calls().await;
+        }
+    });
+
+    Ok(ServerHandle {
+        local_addr,
+        server,
+        acceptor,
+        reaper,
+        local_connections: Mutex::new(JoinSet::new()),
+    })
+}
+
+/// Connects a client to `server` over in-memory channels. Events are passed as is,
+/// so nothing is serialized; otherwise the client behaves like a WebSocket one and
+/// can talk to any other node of the server.
+///
+/// The connection ends when the client is closed or dropped; it is not tied to any
+/// [`ServerHandle`]. Use [`ServerHandle::connect_local`] to have it closed with the handle.
+pub async fn connect_local(server: &SharedServer, meta: NodeMeta) -> DCClient {
+    let (serve, to_server, from_server) = loopback(server);
+    tokio::spawn(serve);
+
+    DCClient::new_loopback(meta, to_server, from_server).await
+}
+
+/// Builds the server side of an in-memory connection and the ends to hand to the client.
+fn loopback(
+    server: &SharedServer,
+) -> (
+    impl Future<Output = ()> + Send + 'static,
+    mpsc::UnboundedSender<Event>,
+    mpsc::UnboundedReceiver<Event>,
+) {
+    let (to_server, from_client) = mpsc::unbounded_channel();
+    let (to_client, from_server) = mpsc::unbounded_channel();
+
+    let events = stream::unfold(from_client, |mut rx| async move {
+        rx.recv().await.map(|event| (event, rx))
+    });
+    let serve = handler::serve_client(ClientWriter::Loopback(to_client), events, server.clone());
+
+    (serve, to_server, from_server)
+}
+
+async fn accept_loop(listener: TcpListener, server: SharedServer) {
+    // owning the connections here closes them when the acceptor is aborted
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((stream, _)) = accepted else {
+                    break;
+                };
+                connections.spawn(client_handler(stream, server.clone()));
+            }
+            Some(_) = connections.join_next() => {}
+        }
+    }
+}
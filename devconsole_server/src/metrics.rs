@@ -0,0 +1,465 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use devconsole::ChannelID;
+use log::{error, info, warn};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use crate::server::SharedServer;
+
+// bucket bounds of the broadcast duration histogram, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.000_05, 0.000_1, 0.000_25, 0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.05, 0.1,
+];
+
+/// Time a scraper has to send its request before the connection is dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request (request line and headers) that is read.
+const MAX_REQUEST_LEN: u64 = 8 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DataKind {
+    Text,
+    Binary,
+}
+
+impl DataKind {
+    fn label(&self) -> &'static str {
+        match self {
+            DataKind::Text => "text",
+            DataKind::Binary => "binary",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DropReason {
+    UnknownChannel,
+    UnknownNode,
+    SendFailed,
+}
+
+impl DropReason {
+    fn label(&self) -> &'static str {
+        match self {
+            DropReason::UnknownChannel => "unknown_channel",
+            DropReason::UnknownNode => "unknown_node",
+            DropReason::SendFailed => "send_failed",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counter {
+    messages: u64,
+    bytes: u64,
+}
+
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: [0; LATENCY_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+pub struct Metrics {
+    connections_total: u64,
+    channels_opened_total: u64,
+    /// Names of the open channels. Series are labelled by name rather than ID, so
+    /// that they carry on when a persistent channel is rebound.
+    channel_names: BTreeMap<ChannelID, String>,
+    received: BTreeMap<(String, DataKind), Counter>,
+    delivered: BTreeMap<(String, DataKind), Counter>,
+    /// Keyed by `None` for data sent to channels that do not exist.
+    dropped: BTreeMap<(Option<String>, DropReason), u64>,
+    broadcast_latency: BTreeMap<DataKind, Histogram>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            connections_total: 0,
+            channels_opened_total: 0,
+            channel_names: BTreeMap::new(),
+            received: BTreeMap::new(),
+            delivered: BTreeMap::new(),
+            dropped: BTreeMap::new(),
+            broadcast_latency: BTreeMap::new(),
+        }
+    }
+
+    pub fn on_connection(&mut self) {
+        self.connections_total += 1;
+    }
+
+    pub fn on_channel_opened(&mut self, channel: ChannelID, name: &str) {
+        self.channels_opened_total += 1;
+        self.channel_names.insert(channel, name.to_string());
+    }
+
+    /// Forgets the series of a closed channel, unless another open channel has its name.
+    pub fn on_channel_closed(&mut self, channel: ChannelID) {
+        let Some(name) = self.channel_names.remove(&channel) else {
+            return;
+        };
+        if self.channel_names.values().any(|n| *n == name) {
+            return;
+        }
+        self.received.retain(|(n, _), _| *n != name);
+        self.delivered.retain(|(n, _), _| *n != name);
+        self.dropped
+            .retain(|(n, _), _| n.as_deref() != Some(name.as_str()));
+    }
+
+    pub fn on_received(&mut self, channel: ChannelID, kind: DataKind, bytes: usize) {
+        let Some(name) = self.channel_names.get(&channel) else {
+            return;
+        };
+        let counter = self.received.entry((name.clone(), kind)).or_default();
+        counter.messages += 1;
+        counter.bytes += bytes as u64;
+    }
+
+    pub fn on_delivered(
+        &mut self,
+        channel: ChannelID,
+        kind: DataKind,
+        bytes: usize,
+        listeners: u64,
+    ) {
+        if listeners == 0 {
+            return;
+        }
+        let Some(name) = self.channel_names.get(&channel) else {
+            return;
+        };
+        let counter = self.delivered.entry((name.clone(), kind)).or_default();
+        counter.messages += listeners;
+        counter.bytes += bytes as u64 * listeners;
+    }
+
+    pub fn on_dropped(&mut self, channel: ChannelID, reason: DropReason, count: u64) {
+        if count == 0 {
+            return;
+        }
+        let name = self.channel_names.get(&channel).cloned();
+        *self.dropped.entry((name, reason)).or_default() += count;
+    }
+
+    pub fn observe_broadcast(&mut self, kind: DataKind, elapsed: Duration) {
+        self.broadcast_latency
+            .entry(kind)
+            .or_insert_with(Histogram::new)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Renders the metrics in the Prometheus text format (version 0.0.4).
+    pub fn render(&self, connections: usize, channels: usize) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "devconsole_connections",
+            "gauge",
+            "Currently connected clients",
+        );
+        let _ = writeln!(out, "devconsole_connections {connections}");
+
+        header(
+            &mut out,
+            "devconsole_connections_total",
+            "counter",
+            "Accepted client connections",
+        );
+        let _ = writeln!(
+            out,
+            "devconsole_connections_total {}",
+            self.connections_total
+        );
+
+        header(
+            &mut out,
+            "devconsole_channels",
+            "gauge",
+            "Currently open channels",
+        );
+        let _ = writeln!(out, "devconsole_channels {channels}");
+
+        header(
+            &mut out,
+            "devconsole_channels_opened_total",
+            "counter",
+            "Opened channels",
+        );
+        let _ = writeln!(
+            out,
+            "devconsole_channels_opened_total {}",
+            self.channels_opened_total
+        );
+
+        let counters = [
+            ("received", "sent to a channel by clients", &self.received),
+            (
+                "delivered",
+                "delivered to channel listeners",
+                &self.delivered,
+            ),
+        ];
+        for (direction, help, counters) in counters {
+            let name = format!("devconsole_channel_{direction}_messages_total");
+            header(&mut out, &name, "counter", &format!("Messages {help}"));
+            for ((channel, kind), counter) in counters {
+                let channel = escape_label(channel);
+                let kind = kind.label();
+                let _ = writeln!(
+                    out,
+                    "{name}{{channel=\"{channel}\",kind=\"{kind}\"}} {}",
+                    counter.messages
+                );
+            }
+
+            let name = format!("devconsole_channel_{direction}_bytes_total");
+            header(&mut out, &name, "counter", &format!("Bytes {help}"));
+            for ((channel, kind), counter) in counters {
+                let channel = escape_label(channel);
+                let kind = kind.label();
+                let _ = writeln!(
+                    out,
+                    "{name}{{channel=\"{channel}\",kind=\"{kind}\"}} {}",
+                    counter.bytes
+                );
+            }
+        }
+
+        let name = "devconsole_dropped_messages_total";
+        header(
+            &mut out,
+            name,
+            "counter",
+            "Messages that could not be delivered",
+        );
+        for ((channel, reason), count) in &self.dropped {
+            let reason = reason.label();
+            match channel {
+                Some(channel) => {
+                    let channel = escape_label(channel);
+                    let _ = writeln!(
+                        out,
+                        "{name}{{channel=\"{channel}\",reason=\"{reason}\"}} {count}"
+                    );
+                }
+                None => {
+                    let _ = writeln!(out, "{name}{{reason=\"{reason}\"}} {count}");
+                }
+            }
+        }
+
+        let name = "devconsole_broadcast_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "Time spent broadcasting one message",
+        );
+        for (kind, histogram) in &self.broadcast_latency {
+            let kind = kind.label();
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{kind=\"{kind}\",le=\"{bound}\"}} {count}"
+                );
+            }
+            let count = histogram.count;
+            let _ = writeln!(out, "{name}_bucket{{kind=\"{kind}\",le=\"+Inf\"}} {count}");
+            let _ = writeln!(out, "{name}_sum{{kind=\"{kind}\"}} {}", histogram.sum);
+            let _ = writeln!(out, "{name}_count{{kind=\"{kind}\"}} {count}");
+        }
+
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Reads the request line, skipping the headers.
+async fn read_request(mut reader: impl AsyncBufReadExt + Unpin) -> std::io::Result<String> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+
+    let mut line = String::new();
+    while reader.read_line(&mut line).await? > 2 {
+        line.clear();
+    }
+
+    Ok(request_line)
+}
+
+async fn http_handler(mut stream: TcpStream, server: SharedServer) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.split();
+    // a request longer than the cap ends where it is cut off
+    let reader = BufReader::new(reader.take(MAX_REQUEST_LEN));
+    let request_line = match timeout(REQUEST_TIMEOUT, read_request(reader)).await {
+        Ok(request_line) => request_line?,
+        Err(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "no request received",
+            ));
+        }
+    };
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = server.render_metrics().await;
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+pub async fn serve(addr: &str, server: SharedServer) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind metrics endpoint on {addr}: {e}");
+            return;
+        }
+    };
+    info!("Serving metrics on http://{addr}/metrics");
+
+    while let Ok((stream, _)) = listener.accept().await {
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = http_handler(stream, server).await {
+                warn!("Error serving metrics request: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(metrics: &Metrics) -> Vec<String> {
+        metrics.render(2, 1).lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn renders_series_labelled_by_channel_name() {
+        let mut metrics = Metrics::new();
+        metrics.on_connection();
+        metrics.on_channel_opened(1, "Foo");
+        metrics.on_received(1, DataKind::Text, 5);
+        metrics.on_delivered(1, DataKind::Text, 5, 2);
+        metrics.on_dropped(1, DropReason::SendFailed, 1);
+        metrics.observe_broadcast(DataKind::Text, Duration::from_micros(200));
+
+        let lines = lines(&metrics);
+        for expected in [
+            "# TYPE devconsole_connections gauge",
+            "devconsole_connections 2",
+            "devconsole_connections_total 1",
+            "devconsole_channels 1",
+            "devconsole_channels_opened_total 1",
+            "devconsole_channel_received_messages_total{channel=\"Foo\",kind=\"text\"} 1",
+            "devconsole_channel_received_bytes_total{channel=\"Foo\",kind=\"text\"} 5",
+            "devconsole_channel_delivered_messages_total{channel=\"Foo\",kind=\"text\"} 2",
+            "devconsole_channel_delivered_bytes_total{channel=\"Foo\",kind=\"text\"} 10",
+            "devconsole_dropped_messages_total{channel=\"Foo\",reason=\"send_failed\"} 1",
+            "# TYPE devconsole_broadcast_duration_seconds histogram",
+            "devconsole_broadcast_duration_seconds_bucket{kind=\"text\",le=\"0.0001\"} 0",
+            "devconsole_broadcast_duration_seconds_bucket{kind=\"text\",le=\"0.00025\"} 1",
+            "devconsole_broadcast_duration_seconds_bucket{kind=\"text\",le=\"+Inf\"} 1",
+            "devconsole_broadcast_duration_seconds_count{kind=\"text\"} 1",
+        ] {
+            assert!(lines.iter().any(|line| line == expected), "{expected}");
+        }
+    }
+
+    #[test]
+    fn unknown_channels_are_not_labelled() {
+        let mut metrics = Metrics::new();
+        metrics.on_received(4242, DataKind::Binary, 3);
+        metrics.on_dropped(4242, DropReason::UnknownChannel, 1);
+
+        let rendered = metrics.render(0, 0);
+        assert!(!rendered.contains("4242"), "{rendered}");
+        assert!(
+            rendered
+                .lines()
+                .any(|line| line
+                    == "devconsole_dropped_messages_total{reason=\"unknown_channel\"} 1"),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn closed_channels_are_pruned() {
+        let mut metrics = Metrics::new();
+        metrics.on_channel_opened(1, "Foo");
+        metrics.on_channel_opened(2, "Foo");
+        metrics.on_received(1, DataKind::Text, 1);
+        metrics.on_dropped(1, DropReason::SendFailed, 1);
+
+        // another channel still has the name
+        metrics.on_channel_closed(1);
+        assert!(metrics.render(0, 1).contains("channel=\"Foo\""));
+
+        metrics.on_channel_closed(2);
+        assert!(!metrics.render(0, 0).contains("channel=\"Foo\""));
+    }
+
+    #[test]
+    fn escapes_channel_names() {
+        let mut metrics = Metrics::new();
+        metrics.on_channel_opened(1, "a\"b\\c");
+        metrics.on_received(1, DataKind::Text, 1);
+
+        assert!(
+            metrics
+                .render(0, 1)
+                .contains("{channel=\"a\\\"b\\\\c\",kind=\"text\"} 1")
+        );
+    }
+}
@@ -1,15 +1,39 @@
-use devconsole::{ChannelID, Event, NodeID};
+use devconsole::{CallID, ChannelID, Event, NodeID, NodeMeta};
 use futures_util::lock::Mutex;
-use std::sync::Arc;
+use log::{info, warn};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::time::Instant;
 
-use crate::{channel::Channel, client::SharedClient, id_manager::IDManager};
+use crate::{
+    channel::Channel,
+    client::SharedClient,
+    id_manager::IDManager,
+    metrics::{DataKind, DropReason, Metrics},
+};
+
+/// How long a persistent channel outlives its supplier.
+pub const PERSISTENT_CHANNEL_GRACE: Duration = Duration::from_secs(60);
+
+/// How long an RPC call is kept when the caller did not say how long it waits.
+pub const RPC_CALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct PendingCall {
+    caller: NodeID,
+    call_id: CallID,
+    responder: NodeID,
+    deadline: Instant,
+}
 
 struct Server {
     node_id_manager: IDManager<NodeID>,
     channel_id_manager: IDManager<ChannelID>,
+    call_id_manager: IDManager<CallID>,
     channels: Vec<Channel>,
 
     connections: Vec<SharedClient>,
+    pending_calls: HashMap<CallID, PendingCall>,
+
+    metrics: Metrics,
 }
 
 #[derive(Clone)]
@@ -19,8 +43,11 @@ impl SharedServer {
         SharedServer(Arc::new(Mutex::new(Server {
             node_id_manager: IDManager::new(),
             channel_id_manager: IDManager::new(),
+            call_id_manager: IDManager::new(),
             channels: Vec::new(),
             connections: Vec::new(),
+            pending_calls: HashMap::new(),
+            metrics: Metrics::new(),
         })))
     }
 
@@ -28,46 +55,195 @@ impl SharedServer {
         self.0.lock().await.node_id_manager.get_new_id()
     }
 
-    pub async fn new_channel(&self, name: String, supplied_by: NodeID) -> ChannelID {
+    pub async fn new_channel(
+        &self,
+        name: String,
+        supplied_by: NodeID,
+        persistent: bool,
+        schema: Option<String>,
+    ) -> ChannelID {
+        if persistent
+            && let Some(cid) = self
+                .rebind_channel(&name, supplied_by, schema.clone())
+                .await
+        {
+            return cid;
+        }
+
         let cid = self.0.lock().await.channel_id_manager.get_new_id();
 
-        let channel = Channel::new(cid, name, supplied_by);
-        self.0.lock().await.channels.push(channel);
+        let channel = Channel::new(cid, name, supplied_by, persistent, schema);
+        {
+            let mut server = self.0.lock().await;
+            server.metrics.on_channel_opened(cid, channel.name());
+            server.channels.push(channel.clone());
+        }
+
+        let info = channel.info(self.get_node_meta(supplied_by).await);
+        self.notify_watchers(Event::ChannelOpened(info)).await;
 
         cid
     }
 
-    pub async fn broadcast_data(&self, channel: ChannelID, data: String, from: NodeID) {
+    async fn rebind_channel(
+        &self,
+        name: &str,
+        supplied_by: NodeID,
+        schema: Option<String>,
+    ) -> Option<ChannelID> {
+        let cid = {
+            let mut server = self.0.lock().await;
+            let channel = server
+                .channels
+                .iter_mut()
+                .find(|c| c.is_persistent() && c.orphaned_since().is_some() && c.name() == name)?;
+            channel.rebind(supplied_by, schema);
+            channel.id()
+        };
+
+        info!("Persistent channel {cid} ({name}) re-bound to node {supplied_by}");
+        self.notify_listeners(
+            cid,
+            Event::ChannelSupplierUp {
+                channel: cid,
+                supplied_by,
+            },
+        )
+        .await;
+
+        Some(cid)
+    }
+
+    async fn notify_watchers(&self, event: Event) {
         for client in &self.0.lock().await.connections {
-            if client.node_id().await == from {
-                continue;
+            if client.is_watching_channels().await {
+                let _ = client.send_event(event.clone()).await;
             }
+        }
+    }
+
+    async fn notify_listeners(&self, channel: ChannelID, event: Event) {
+        for client in &self.0.lock().await.connections {
             if client.is_listening(channel).await {
-                let event = Event::Data {
-                    channel,
-                    data: data.clone(),
-                };
-                client.send_event(event).await.unwrap();
+                let _ = client.send_event(event.clone()).await;
             }
         }
     }
 
+    pub async fn broadcast_data(&self, channel: ChannelID, data: String, from: NodeID) {
+        let len = data.len();
+        self.deliver(channel, DataKind::Text, len, from, None, || Event::Data {
+            channel,
+            from,
+            data: data.clone(),
+        })
+        .await;
+    }
+
     pub async fn broadcast_bin_data(&self, channel: ChannelID, data: Vec<u8>, from: NodeID) {
-        for client in &self.0.lock().await.connections {
-            if client.node_id().await == from {
-                continue;
+        let len = data.len();
+        self.deliver(channel, DataKind::Binary, len, from, None, || {
+            Event::DataBin {
+                channel,
+                from,
+                data: data.clone(),
             }
-            if client.is_listening(channel).await {
-                let event = Event::DataBin {
-                    channel,
-                    data: data.clone(),
-                };
-                client.send_event(event).await.unwrap();
+        })
+        .await;
+    }
+
+    /// Delivers data on `channel` to the node `to` only, whether it listens or not.
+    pub async fn unicast_data(&self, channel: ChannelID, data: String, from: NodeID, to: NodeID) {
+        let len = data.len();
+        self.deliver(channel, DataKind::Text, len, from, Some(to), || {
+            Event::Direct {
+                to,
+                from,
+                channel,
+                data: data.clone(),
+            }
+        })
+        .await;
+    }
+
+    pub async fn unicast_bin_data(
+        &self,
+        channel: ChannelID,
+        data: Vec<u8>,
+        from: NodeID,
+        to: NodeID,
+    ) {
+        let len = data.len();
+        self.deliver(channel, DataKind::Binary, len, from, Some(to), || {
+            Event::DirectBin {
+                to,
+                from,
+                channel,
+                data: data.clone(),
+            }
+        })
+        .await;
+    }
+
+    async fn deliver(
+        &self,
+        channel: ChannelID,
+        kind: DataKind,
+        len: usize,
+        from: NodeID,
+        to: Option<NodeID>,
+        event: impl Fn() -> Event,
+    ) {
+        let mut server = self.0.lock().await;
+        // timed from here so that waiting for the lock is not counted
+        let started_at = Instant::now();
+
+        server.metrics.on_received(channel, kind, len);
+        if !server.channels.iter().any(|c| c.id() == channel) {
+            warn!("Data sent to unknown channel {channel} by node {from}");
+            server
+                .metrics
+                .on_dropped(channel, DropReason::UnknownChannel, 1);
+            return;
+        }
+
+        let mut delivered = 0;
+        let mut dropped = 0;
+        for client in &server.connections {
+            let node_id = client.node_id().await;
+            let is_target = match to {
+                Some(to) => node_id == to,
+                None => node_id != from && client.is_listening(channel).await,
+            };
+            if is_target {
+                match client.send_event(event()).await {
+                    Ok(()) => delivered += 1,
+                    Err(_) => dropped += 1,
+                }
             }
         }
+
+        server.metrics.on_delivered(channel, kind, len, delivered);
+        server
+            .metrics
+            .on_dropped(channel, DropReason::SendFailed, dropped);
+
+        match to {
+            None => server.metrics.observe_broadcast(kind, started_at.elapsed()),
+            Some(to) if delivered + dropped == 0 => {
+                warn!("Direct data on channel {channel} to unknown node {to}");
+                server
+                    .metrics
+                    .on_dropped(channel, DropReason::UnknownNode, 1);
+            }
+            Some(_) => {}
+        }
     }
+
     pub async fn add_connection(&self, client: SharedClient) {
-        self.0.lock().await.connections.push(client);
+        let mut server = self.0.lock().await;
+        server.metrics.on_connection();
+        server.connections.push(client);
     }
 
     pub async fn remove_connection(&self, client: &SharedClient) {
@@ -75,12 +251,236 @@ impl SharedServer {
 
         self.0.lock().await.connections.retain(|c| c != client);
 
-        // remove channels that are provided by this client
-        self.0
-            .lock()
-            .await
-            .channels
-            .retain(|c| c.supplied_by() != client_node_id);
+        // remove channels that are provided by this client,
+        // persistent ones are kept until the grace period expires
+        let (closed, orphaned): (Vec<Channel>, Vec<ChannelID>) = {
+            let mut server = self.0.lock().await;
+            let closed: Vec<Channel> = server
+                .channels
+                .iter()
+                .filter(|c| !c.is_persistent() && c.supplied_by() == client_node_id)
+                .cloned()
+                .collect();
+            server
+                .channels
+                .retain(|c| c.is_persistent() || c.supplied_by() != client_node_id);
+            for channel in &closed {
+                server.metrics.on_channel_closed(channel.id());
+            }
+            let orphaned = server
+                .channels
+                .iter_mut()
+                .filter(|c| c.supplied_by() == client_node_id && c.orphaned_since().is_none())
+                .map(|c| {
+                    c.orphan();
+                    c.id()
+                })
+                .collect();
+            (closed, orphaned)
+        };
+
+        let supplier = client.meta().await;
+        for channel in closed {
+            let info = channel.info(supplier.clone());
+            self.notify_watchers(Event::ChannelClosed(info)).await;
+        }
+
+        for channel in orphaned {
+            info!("Supplier of persistent channel {channel} disconnected");
+            self.notify_listeners(channel, Event::ChannelSupplierDown { channel })
+                .await;
+        }
+
+        // fail calls that were waiting for this client, forget calls made by it
+        let failed: Vec<PendingCall> = {
+            let mut server = self.0.lock().await;
+            server
+                .pending_calls
+                .retain(|_, call| call.caller != client_node_id);
+            let ids: Vec<CallID> = server
+                .pending_calls
+                .iter()
+                .filter(|(_, call)| call.responder == client_node_id)
+                .map(|(id, _)| *id)
+                .collect();
+            ids.iter()
+                .filter_map(|id| server.pending_calls.remove(id))
+                .collect()
+        };
+
+        for call in failed {
+            let response = Event::RpcResponse {
+                call_id: call.call_id,
+                result: Err(format!("Node {client_node_id} disconnected")),
+            };
+            let _ = self.unicast(call.caller, response).await;
+        }
+    }
+
+    async fn find_client(&self, node_id: NodeID) -> Option<SharedClient> {
+        for client in &self.0.lock().await.connections {
+            if client.node_id().await == node_id {
+                return Some(client.clone());
+            }
+        }
+        None
+    }
+
+    /// Sends an event to a single node.
+    async fn unicast(&self, node_id: NodeID, event: Event) -> Result<(), String> {
+        match self.find_client(node_id).await {
+            Some(client) => client.send_event(event).await,
+            None => Err(format!("Node {node_id} is not connected")),
+        }
+    }
+
+    /// Forwards an RPC request to the supplier of `channel`. The call is forgotten
+    /// once `timeout` has passed without a response.
+    pub async fn route_rpc_request(
+        &self,
+        caller: NodeID,
+        channel: ChannelID,
+        call_id: CallID,
+        data: Vec<u8>,
+        timeout: Duration,
+    ) {
+        let route = {
+            let mut server = self.0.lock().await;
+            match server.channels.iter().find(|c| c.id() == channel) {
+                None => Err(format!("Unknown channel {channel}")),
+                Some(c) if c.orphaned_since().is_some() => {
+                    Err(format!("Supplier of channel {channel} is down"))
+                }
+                Some(c) => {
+                    let responder = c.supplied_by();
+                    let routed_id = server.call_id_manager.get_new_id();
+                    server.pending_calls.insert(
+                        routed_id,
+                        PendingCall {
+                            caller,
+                            call_id,
+                            responder,
+                            deadline: Instant::now() + timeout,
+                        },
+                    );
+                    Ok((responder, routed_id))
+                }
+            }
+        };
+
+        let error = match route {
+            Ok((responder, routed_id)) => {
+                let request = Event::RpcRequest {
+                    channel,
+                    call_id: routed_id,
+                    data,
+                    timeout_ms: Some(timeout.as_millis() as u64),
+                };
+                match self.unicast(responder, request).await {
+                    Ok(()) => return,
+                    Err(e) => {
+                        self.0.lock().await.pending_calls.remove(&routed_id);
+                        e
+                    }
+                }
+            }
+            Err(e) => e,
+        };
+
+        let response = Event::RpcResponse {
+            call_id,
+            result: Err(error),
+        };
+        let _ = self.unicast(caller, response).await;
+    }
+
+    /// Routes an RPC response back to the caller only.
+    pub async fn route_rpc_response(
+        &self,
+        responder: NodeID,
+        call_id: CallID,
+        result: Result<Vec<u8>, String>,
+    ) {
+        let call = {
+            let mut server = self.0.lock().await;
+            match server.pending_calls.get(&call_id) {
+                Some(call) if call.responder == responder => server.pending_calls.remove(&call_id),
+                _ => None,
+            }
+        };
+
+        let Some(call) = call else {
+            warn!("Unexpected RPC response {call_id} from node {responder}");
+            return;
+        };
+
+        let response = Event::RpcResponse {
+            call_id: call.call_id,
+            result,
+        };
+        let _ = self.unicast(call.caller, response).await;
+    }
+
+    /// Fails RPC calls whose deadline has passed without a response.
+    pub async fn expire_pending_calls(&self) {
+        let now = Instant::now();
+        let expired: Vec<PendingCall> = {
+            let mut server = self.0.lock().await;
+            let ids: Vec<CallID> = server
+                .pending_calls
+                .iter()
+                .filter(|(_, call)| call.deadline <= now)
+                .map(|(id, _)| *id)
+                .collect();
+            ids.iter()
+                .filter_map(|id| server.pending_calls.remove(id))
+                .collect()
+        };
+
+        for call in expired {
+            info!(
+                "RPC call {} of node {} to node {} timed out",
+                call.call_id, call.caller, call.responder
+            );
+            let response = Event::RpcResponse {
+                call_id: call.call_id,
+                result: Err(format!("Node {} did not respond in time", call.responder)),
+            };
+            let _ = self.unicast(call.caller, response).await;
+        }
+    }
+
+    /// Removes persistent channels whose supplier has been gone longer than `grace`.
+    pub async fn expire_orphaned_channels(&self, grace: Duration) {
+        let expired: Vec<Channel> = {
+            let mut server = self.0.lock().await;
+            let (expired, kept) = std::mem::take(&mut server.channels)
+                .into_iter()
+                .partition(|c| {
+                    c.orphaned_since()
+                        .is_some_and(|since| since.elapsed() >= grace)
+                });
+            server.channels = kept;
+            for channel in &expired {
+                server.metrics.on_channel_closed(channel.id());
+            }
+            expired
+        };
+
+        for channel in expired {
+            info!(
+                "Persistent channel {} ({}) expired",
+                channel.id(),
+                channel.name()
+            );
+            // the supplier is gone, so is its meta
+            self.notify_watchers(Event::ChannelClosed(channel.info(None)))
+                .await;
+        }
+    }
+
+    pub async fn get_node_meta(&self, node_id: NodeID) -> Option<NodeMeta> {
+        self.find_client(node_id).await?.meta().await
     }
 
     pub async fn get_channel_ids(&self) -> Vec<ChannelID> {
@@ -102,4 +502,11 @@ impl SharedServer {
             .find(|c| c.id() == channel_id)
             .cloned()
     }
+
+    pub async fn render_metrics(&self) -> String {
+        let server = self.0.lock().await;
+        server
+            .metrics
+            .render(server.connections.len(), server.channels.len())
+    }
 }
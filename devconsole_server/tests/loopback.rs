@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use devconsole::{ConnectionState, NodeMeta};
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn local_client_talks_to_websocket_client() {
+    let server = devconsole_server::spawn_on("127.0.0.1:0").await.unwrap();
+    let local = server.connect_local(NodeMeta::new("local", "test")).await;
+    let remote = devconsole::DCClient::new(&server.url()).await.unwrap();
+
+    let channel = local.open("loopback".to_string()).await.unwrap();
+    let mut sub = remote.subscribe(channel).await.unwrap();
+    local.send(channel, "hello".to_string()).await.unwrap();
+
+    let message = timeout(Duration::from_secs(5), sub.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.as_bytes(), b"hello");
+}
+
+#[tokio::test]
+async fn dropping_handle_closes_local_connections() {
+    let server = devconsole_server::spawn_on("127.0.0.1:0").await.unwrap();
+    let local = server.connect_local(NodeMeta::new("local", "test")).await;
+    local.channel_list().await.unwrap();
+
+    drop(server);
+
+    timeout(Duration::from_secs(5), local.closed())
+        .await
+        .expect("local connection outlived the server handle");
+    assert_eq!(*local.state().borrow(), ConnectionState::Closed);
+}
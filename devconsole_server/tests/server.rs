@@ -0,0 +1,403 @@
+use std::time::Duration;
+
+use devconsole::{
+    ChannelEvent, DCClient, DCClientError, Delivery, Event, Message, NodeID, Subscription,
+    SupplierState,
+};
+use devconsole_server::ServerHandle;
+use futures_util::{SinkExt, StreamExt};
+use tokio::{sync::mpsc, time::timeout};
+use tokio_tungstenite::{connect_async, tungstenite};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+async fn start() -> ServerHandle {
+    devconsole_server::spawn_on("127.0.0.1:0").await.unwrap()
+}
+
+async fn connect(server: &ServerHandle) -> DCClient {
+    DCClient::new(&server.url()).await.unwrap()
+}
+
+/// Node ID of `client`, which is known once the server has answered a request.
+async fn node_id(client: &DCClient) -> NodeID {
+    client.channel_list().await.unwrap();
+    client.get_node_id().await.unwrap()
+}
+
+async fn recv(subscription: &mut Subscription) -> Message {
+    timeout(WAIT, subscription.recv())
+        .await
+        .expect("no message")
+        .expect("subscription ended")
+}
+
+/// Checks that nothing arrives on `subscription` for a while.
+async fn assert_silent(subscription: &mut Subscription) {
+    let received = timeout(Duration::from_millis(200), subscription.recv()).await;
+    assert!(received.is_err(), "unexpected message: {received:?}");
+}
+
+#[tokio::test]
+async fn broadcasts_to_every_listener_on_an_ephemeral_port() {
+    let server = start().await;
+    assert_ne!(server.local_addr().port(), 0);
+
+    let supplier = connect(&server).await;
+    let a = connect(&server).await;
+    let b = connect(&server).await;
+
+    let channel = supplier.open("broadcast".to_string()).await.unwrap();
+    let mut from_a = supplier.subscribe(channel).await.unwrap();
+    let mut sub_a = a.subscribe(channel).await.unwrap();
+    let mut sub_b = b.subscribe(channel).await.unwrap();
+
+    a.send(channel, "from a".to_string()).await.unwrap();
+
+    let supplier_id = node_id(&supplier).await;
+    let a_id = node_id(&a).await;
+    for subscription in [&mut from_a, &mut sub_b] {
+        let message = recv(subscription).await;
+        assert_eq!(message.as_bytes(), b"from a");
+        assert_eq!(message.from(), a_id);
+        assert!(!message.is_direct());
+    }
+    // the sender does not get its own data back
+    assert_silent(&mut sub_a).await;
+
+    supplier
+        .send(channel, "from supplier".to_string())
+        .await
+        .unwrap();
+    for subscription in [&mut sub_a, &mut sub_b] {
+        let message = recv(subscription).await;
+        assert_eq!(message.as_bytes(), b"from supplier");
+        assert_eq!(message.from(), supplier_id);
+    }
+}
+
+#[tokio::test]
+async fn persistent_channel_survives_its_supplier() {
+    let server = start().await;
+    let supplier = connect(&server).await;
+    let listener = connect(&server).await;
+
+    let channel = supplier
+        .open_persistent("persistent".to_string())
+        .await
+        .unwrap();
+    let mut subscription = listener.subscribe(channel).await.unwrap();
+    let (tx, mut states) = mpsc::channel(4);
+    listener.watch_supplier(channel, tx).await;
+
+    supplier.close().await;
+    let state = timeout(WAIT, states.recv()).await.unwrap().unwrap();
+    assert_eq!(state, (channel, SupplierState::Down));
+    assert!(listener.channel_list().await.unwrap().contains(&channel));
+
+    // a new supplier opening the same name takes the channel over
+    let supplier = connect(&server).await;
+    let rebound = supplier
+        .open_persistent("persistent".to_string())
+        .await
+        .unwrap();
+    assert_eq!(rebound, channel);
+    let state = timeout(WAIT, states.recv()).await.unwrap().unwrap();
+    assert_eq!(
+        state,
+        (channel, SupplierState::Up(node_id(&supplier).await))
+    );
+
+    supplier.send(channel, "back".to_string()).await.unwrap();
+    assert_eq!(recv(&mut subscription).await.as_bytes(), b"back");
+
+    // once orphaned for longer than the grace period, the channel is gone
+    supplier.close().await;
+    let state = timeout(WAIT, states.recv()).await.unwrap().unwrap();
+    assert_eq!(state, (channel, SupplierState::Down));
+    server
+        .server()
+        .expire_orphaned_channels(Duration::ZERO)
+        .await;
+    assert!(!listener.channel_list().await.unwrap().contains(&channel));
+}
+
+#[tokio::test]
+async fn plain_channel_closes_with_its_supplier() {
+    let server = start().await;
+    let supplier = connect(&server).await;
+    let listener = connect(&server).await;
+
+    let channel = supplier.open("plain".to_string()).await.unwrap();
+    assert!(listener.channel_list().await.unwrap().contains(&channel));
+
+    supplier.close().await;
+    // the server removes the channel after the connection is gone
+    let gone = async {
+        while listener.channel_list().await.unwrap().contains(&channel) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    timeout(WAIT, gone).await.unwrap();
+}
+
+#[tokio::test]
+async fn watchers_are_told_about_channels_opening_and_closing() {
+    let server = start().await;
+    let watcher = connect(&server).await;
+    let (tx, mut events) = mpsc::unbounded_channel();
+    watcher.watch_channels(tx).await.unwrap();
+    let mut next = async || timeout(WAIT, events.recv()).await.unwrap().unwrap();
+
+    // a channel that lives only briefly is reported all the same
+    let supplier = connect(&server).await;
+    let plain = supplier.open("plain".to_string()).await.unwrap();
+    let persistent = supplier
+        .open_persistent("persistent".to_string())
+        .await
+        .unwrap();
+    supplier.close().await;
+
+    match next().await {
+        ChannelEvent::Opened(info) => {
+            assert_eq!((info.channel, info.name.as_str()), (plain, "plain"))
+        }
+        event => panic!("unexpected event: {event:?}"),
+    }
+    match next().await {
+        ChannelEvent::Opened(info) => assert_eq!(info.channel, persistent),
+        event => panic!("unexpected event: {event:?}"),
+    }
+    match next().await {
+        ChannelEvent::Closed(info) => assert_eq!(info.channel, plain),
+        event => panic!("unexpected event: {event:?}"),
+    }
+
+    // re-binding the persistent channel is neither a close nor an open
+    let supplier = connect(&server).await;
+    supplier
+        .open_persistent("persistent".to_string())
+        .await
+        .unwrap();
+    let other = supplier.open("other".to_string()).await.unwrap();
+    match next().await {
+        ChannelEvent::Opened(info) => assert_eq!(info.channel, other),
+        event => panic!("unexpected event: {event:?}"),
+    }
+
+    supplier.close().await;
+    match next().await {
+        ChannelEvent::Closed(info) => assert_eq!(info.channel, other),
+        event => panic!("unexpected event: {event:?}"),
+    }
+    server
+        .server()
+        .expire_orphaned_channels(Duration::ZERO)
+        .await;
+    match next().await {
+        ChannelEvent::Closed(info) => assert_eq!(info.channel, persistent),
+        event => panic!("unexpected event: {event:?}"),
+    }
+}
+
+#[tokio::test]
+async fn rpc_calls_are_routed_to_the_supplier() {
+    let server = start().await;
+    let responder = connect(&server).await;
+    let caller = connect(&server).await;
+    let bystander = connect(&server).await;
+
+    let channel = responder.open("rpc".to_string()).await.unwrap();
+    responder
+        .serve(channel, |data| async move {
+            match data.as_slice() {
+                b"fail" => Err("refused".to_string()),
+                _ => Ok(data.into_iter().rev().collect()),
+            }
+        })
+        .await;
+    let mut overheard = bystander.subscribe(channel).await.unwrap();
+
+    let reply = caller.call(channel, b"abc".to_vec(), WAIT).await.unwrap();
+    assert_eq!(reply, b"cba");
+
+    match caller.call(channel, b"fail".to_vec(), WAIT).await {
+        Err(DCClientError::RpcError(reason)) => assert_eq!(reason, "refused"),
+        other => panic!("unexpected result: {other:?}"),
+    }
+
+    // neither the request nor the reply is broadcast to listeners
+    assert_silent(&mut overheard).await;
+
+    match caller.call(12345, b"abc".to_vec(), WAIT).await {
+        Err(DCClientError::RpcError(reason)) => assert!(reason.contains("Unknown channel")),
+        other => panic!("unexpected result: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn rpc_call_fails_when_the_responder_disconnects() {
+    let server = start().await;
+    let responder = connect(&server).await;
+    let caller = connect(&server).await;
+
+    let channel = responder.open("rpc".to_string()).await.unwrap();
+    responder
+        .serve(channel, |_| {
+            std::future::pending::<Result<Vec<u8>, String>>()
+        })
+        .await;
+
+    let call = tokio::spawn({
+        let caller = caller.clone();
+        async move { caller.call(channel, Vec::new(), WAIT).await }
+    });
+    // let the request reach the responder before it goes away
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    responder.close().await;
+
+    match call.await.unwrap() {
+        Err(DCClientError::RpcError(reason)) => assert!(reason.contains("disconnected")),
+        other => panic!("unexpected result: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn unanswered_rpc_call_expires_on_the_server() {
+    let server = start().await;
+    let responder = connect(&server).await;
+    let channel = responder.open("rpc".to_string()).await.unwrap();
+    responder
+        .serve(channel, |_| {
+            std::future::pending::<Result<Vec<u8>, String>>()
+        })
+        .await;
+
+    // a raw connection, so the caller itself never gives up on the call
+    let (mut ws, _) = connect_async(server.url()).await.unwrap();
+    let request = Event::RpcRequest {
+        channel,
+        call_id: 7,
+        data: Vec::new(),
+        timeout_ms: Some(100),
+    };
+    let request = serde_json::to_string(&request).unwrap();
+    ws.send(tungstenite::Message::Text(request.into()))
+        .await
+        .unwrap();
+
+    let response = async {
+        while let Some(message) = ws.next().await {
+            let message = message.unwrap();
+            let Ok(event) = serde_json::from_str::<Event>(message.to_text().unwrap()) else {
+                continue;
+            };
+            if let Event::RpcResponse { call_id, result } = event {
+                return (call_id, result);
+            }
+        }
+        panic!("connection closed before the call expired");
+    };
+    let (call_id, result) = timeout(WAIT, response).await.unwrap();
+    assert_eq!(call_id, 7);
+    assert!(result.unwrap_err().contains("did not respond in time"));
+}
+
+#[tokio::test]
+async fn direct_data_reaches_only_the_target() {
+    let server = start().await;
+    let sender = connect(&server).await;
+    let target = connect(&server).await;
+    let listener = connect(&server).await;
+
+    let channel = sender.open("direct".to_string()).await.unwrap();
+    let mut directs = target.directs().await.unwrap();
+    let mut subscription = listener.subscribe(channel).await.unwrap();
+
+    let target_id = node_id(&target).await;
+    sender
+        .send_direct(target_id, channel, "psst".to_string())
+        .await
+        .unwrap();
+
+    // delivered although the target does not listen to the channel
+    let message = timeout(WAIT, directs.recv()).await.unwrap().unwrap();
+    assert_eq!(message.as_bytes(), b"psst");
+    assert_eq!(message.channel(), channel);
+    assert_eq!(message.from(), node_id(&sender).await);
+    assert!(message.is_direct());
+
+    assert_silent(&mut subscription).await;
+}
+
+#[tokio::test]
+async fn subscriptions_of_one_client_share_the_channel() {
+    let server = start().await;
+    let supplier = connect(&server).await;
+    let client = connect(&server).await;
+
+    let channel = supplier.open("fanout".to_string()).await.unwrap();
+    let (first, second) = tokio::join!(client.subscribe(channel), client.subscribe(channel));
+    let (mut first, mut second) = (first.unwrap(), second.unwrap());
+
+    supplier.send(channel, "one".to_string()).await.unwrap();
+    assert_eq!(recv(&mut first).await.as_bytes(), b"one");
+    assert_eq!(recv(&mut second).await.as_bytes(), b"one");
+
+    // the client keeps listening while a subscription is left
+    drop(first);
+    supplier.send(channel, "two".to_string()).await.unwrap();
+    assert_eq!(recv(&mut second).await.as_bytes(), b"two");
+
+    // and listens again for a new one after the last is gone
+    drop(second);
+    let mut third = client.subscribe(channel).await.unwrap();
+    supplier.send(channel, "three".to_string()).await.unwrap();
+    assert_eq!(recv(&mut third).await.as_bytes(), b"three");
+}
+
+#[tokio::test]
+async fn lagging_subscription_counts_dropped_messages() {
+    let server = start().await;
+    let supplier = connect(&server).await;
+    let client = connect(&server).await;
+
+    let channel = supplier.open("lag".to_string()).await.unwrap();
+    let marker = supplier.open("marker".to_string()).await.unwrap();
+    let mut lagging = client
+        .subscribe_with(channel, Delivery::DropOldest(2))
+        .await
+        .unwrap();
+    let mut done = client.subscribe(marker).await.unwrap();
+
+    for i in 0..10 {
+        supplier.send(channel, i.to_string()).await.unwrap();
+    }
+    // the server forwards in order, so the data is in once the marker arrives
+    supplier.send(marker, "done".to_string()).await.unwrap();
+    recv(&mut done).await;
+
+    assert_eq!(lagging.dropped(), 8);
+    assert_eq!(recv(&mut lagging).await.as_bytes(), b"8");
+    assert_eq!(recv(&mut lagging).await.as_bytes(), b"9");
+}
+
+#[tokio::test]
+async fn data_to_unknown_channel_is_counted_as_dropped() {
+    let server = start().await;
+    let client = connect(&server).await;
+
+    client.send(4242, "lost".to_string()).await.unwrap();
+    // requests are handled in order, so the data has been seen after this
+    client.channel_list().await.unwrap();
+
+    let metrics = server.server().render_metrics().await;
+    // IDs made up by clients are not turned into labels
+    assert!(
+        metrics
+            .lines()
+            .any(|line| line == "devconsole_dropped_messages_total{reason=\"unknown_channel\"} 1"),
+        "{metrics}"
+    );
+    assert!(!metrics.contains("4242"), "{metrics}");
+}
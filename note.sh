@@ -1,8 +1,8 @@
-target/debug/devconsole_cli send SerialMonitor '{"OpenVPort":{"path":"/dev/ttyACM0","channel_name":"MCU"}}'
-target/debug/devconsole_cli send PktUART '{"src":3,"dst_ch_name":"MCU-PktUART"}'
-target/debug/devconsole_cli listen MCU-PktUART
+target/debug/devconsole_cli serial open-vport /dev/ttyACM0 MCU
+target/debug/devconsole_cli pktuart bridge MCU MCU-PktUART
+target/debug/devconsole_cli pktuart listen MCU-PktUART
 
-target/debug/devconsole_cli send -b MCU-PktUART "\nabc"
+target/debug/devconsole_cli pktuart send MCU-PktUART 0x0a "abc"
 
 
 KV: [id, data...] (data: [] ==> Request, otherwise ==> Response)